@@ -0,0 +1,49 @@
+//! Compare batch slice conversion against the per-sample path.
+//!
+//! Run with `cargo bench --bench batch`.
+
+use mkaudio::{AudioBuffer, Layout, SampleFormat};
+
+const CHANNELS : u32 = 64;
+const FRAMES : u32 = 4096;
+const ROUNDS : u32 = 50;
+
+fn measure(name : &str, mut run : impl FnMut())
+{
+    run();
+    let start = std::time::Instant::now();
+    for _ in 0..ROUNDS { run(); }
+    let elapsed = start.elapsed() / ROUNDS;
+    let rate = (CHANNELS * FRAMES) as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{name:<40} {elapsed:>12.2?} {rate:>10.1} Msamples/s");
+}
+
+fn main()
+{
+    let samples : Vec<f64> = (0..CHANNELS * FRAMES).map(|index| (index as f64 * 0.001).sin()).collect();
+    let narrow : Vec<f32> = samples.iter().map(|sample| *sample as f32).collect();
+    for (format, bit_depth) in [(SampleFormat::Int, 16), (SampleFormat::Int, 24), (SampleFormat::Int, 32), (SampleFormat::Float, 32)]
+    {
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            let mut buffer = AudioBuffer::init_with_format(CHANNELS, bit_depth, FRAMES, format).unwrap();
+            buffer.set_layout(layout);
+            let mut output = vec![0.0; samples.len()];
+            let mut narrow_output = vec![0.0_f32; samples.len()];
+            let label = format!("{format:?} {bit_depth} {layout:?}");
+            measure(&format!("{label} write per sample"), ||
+            {
+                for (index, sample) in samples.iter().enumerate() { buffer.write(index as u32, *sample); }
+            });
+            measure(&format!("{label} write_slice f64"), || buffer.write_slice(std::hint::black_box(&samples)).unwrap());
+            measure(&format!("{label} write_slice_as f32"), || buffer.write_slice_as(std::hint::black_box(&narrow)).unwrap());
+            measure(&format!("{label} read per sample"), ||
+            {
+                for (index, sample) in output.iter_mut().enumerate() { *sample = buffer.read(index as u32); }
+                std::hint::black_box(&output);
+            });
+            measure(&format!("{label} read_slice f64"), || buffer.read_slice(std::hint::black_box(&mut output)).unwrap());
+            measure(&format!("{label} read_slice_as f32"), || buffer.read_slice_as(std::hint::black_box(&mut narrow_output)).unwrap());
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Batch conversion between stored bytes and runs of native samples.
+//!
+//! Common encodings get a tight loop over fixed size chunks with the scale folded into a constant so the compiler can
+//! vectorize it. Samples stored exactly as their native type are copied without the f64 round trip. Everything else
+//! falls back to the per-sample path of `Encoding`, which gives the same results.
+
+use crate::{ClipPolicy, Encoding, Endian, Sample, SampleFormat};
+
+/// Decode consecutive stored samples into the given samples.
+pub(crate) fn decode<'a, S : Sample>(encoding : Encoding, bytes : &[u8], samples : impl Iterator<Item = &'a mut S>)
+{
+    let little = encoding.endian == Endian::Little;
+    if encoding.stores::<S>() { return native_decode(encoding.size(), bytes, samples, little); }
+    if encoding.container != encoding.bit_depth { return fallback_decode(encoding, bytes, samples); }
+    match (encoding.format, encoding.bit_depth)
+    {
+        (SampleFormat::Int, 8) => load(bytes, samples, little, |[a] : [u8; 1]| a as i8 as f64 * scale(8)),
+        (SampleFormat::UInt, 8) => load(bytes, samples, little, |[a] : [u8; 1]| (a as i32 - 128) as f64 * scale(8)),
+        (SampleFormat::Int, 16) => load(bytes, samples, little, |data| i16::from_le_bytes(data) as f64 * scale(16)),
+        (SampleFormat::Int, 24) => load(bytes, samples, little, |[a, b, c]| (i32::from_le_bytes([0, a, b, c]) >> 8) as f64 * scale(24)),
+        (SampleFormat::Int, 32) => load(bytes, samples, little, |data| i32::from_le_bytes(data) as f64 * scale(32)),
+        (SampleFormat::Float, 32) => load(bytes, samples, little, |data| f32::from_le_bytes(data) as f64),
+        (SampleFormat::Float, 64) => load(bytes, samples, little, f64::from_le_bytes),
+        _ => fallback_decode(encoding, bytes, samples)
+    }
+}
+/// Encode the given samples into consecutive stored samples.
+///
+/// Returns how many samples were out of range, or the position of the first rejected sample with `ClipPolicy::Error`.
+/// Saturating matches `sample::int_clip`: the saturating float cast already clamps to the native integer widths, and
+/// clamping 24-bit samples before the cast is the same as after because truncation cannot leave the range.
+pub(crate) fn encode<'a, S : Sample>(encoding : Encoding, samples : impl Iterator<Item = &'a S>, bytes : &mut [u8]) -> Result<u64, usize>
+{
+    let little = encoding.endian == Endian::Little;
+    if encoding.stores::<S>()
+    {
+        native_encode(encoding.size(), samples, bytes, little);
+        return Ok(0);
+    }
+    if encoding.container != encoding.bit_depth || (encoding.clip != ClipPolicy::Saturate && encoding.format != SampleFormat::Float)
+    {
+        return fallback_encode(encoding, samples, bytes);
+    }
+    let clips = match (encoding.format, encoding.bit_depth)
+    {
+        (SampleFormat::Int, 8) => store(samples, bytes, little, clips(8), |value| [(value * 128.0) as i8 as u8]),
+        (SampleFormat::UInt, 8) => store(samples, bytes, little, clips(8), |value| [(value * 128.0) as i8 as u8 ^ 0x80]),
+        (SampleFormat::Int, 16) => store(samples, bytes, little, clips(16), |value| ((value * 32768.0) as i16).to_le_bytes()),
+        (SampleFormat::Int, 24) =>
+        {
+            store(samples, bytes, little, clips(24), |value|
+            {
+                let [a, b, c, _] = ((value * 8388608.0).clamp(-8388608.0, 8388607.0) as i32).to_le_bytes();
+                [a, b, c]
+            })
+        }
+        (SampleFormat::Int, 32) => store(samples, bytes, little, clips(32), |value| ((value * 2147483648.0) as i32).to_le_bytes()),
+        (SampleFormat::Float, 32) => store(samples, bytes, little, |_| false, |value| (value as f32).to_le_bytes()),
+        (SampleFormat::Float, 64) => store(samples, bytes, little, |_| false, f64::to_le_bytes),
+        _ => return fallback_encode(encoding, samples, bytes)
+    };
+    Ok(clips)
+}
+/// Reciprocal of the integer limit, exact because it is a power of two.
+const fn scale(bit_depth : u32) -> f64 { 1.0 / (1_u64 << (bit_depth - 1)) as f64 }
+/// Check whether a normalized sample truncates out of the integer range, exact because the limit is a power of two.
+fn clips(bit_depth : u32) -> impl Fn(f64) -> bool
+{
+    let limit = (1_u64 << (bit_depth - 1)) as f64;
+    move |value| value * limit >= limit || value * limit <= -limit - 1.0
+}
+
+fn load<'a, S : Sample, const N : usize>(bytes : &[u8], samples : impl Iterator<Item = &'a mut S>, little : bool, read : impl Fn([u8; N]) -> f64)
+{
+    let (chunks, _) = bytes.as_chunks::<N>();
+    if little
+    {
+        for (chunk, sample) in chunks.iter().zip(samples) { *sample = S::from_f64(read(*chunk)); }
+    }
+    else
+    {
+        for (chunk, sample) in chunks.iter().zip(samples)
+        {
+            let mut chunk = *chunk;
+            chunk.reverse();
+            *sample = S::from_f64(read(chunk));
+        }
+    }
+}
+fn store<'a, S : Sample, const N : usize>(samples : impl Iterator<Item = &'a S>, bytes : &mut [u8], little : bool, clips : impl Fn(f64) -> bool, write : impl Fn(f64) -> [u8; N]) -> u64
+{
+    let (chunks, _) = bytes.as_chunks_mut::<N>();
+    let mut count = 0;
+    if little
+    {
+        for (chunk, sample) in chunks.iter_mut().zip(samples)
+        {
+            let value = sample.to_f64();
+            count += clips(value) as u64;
+            *chunk = write(value);
+        }
+    }
+    else
+    {
+        for (chunk, sample) in chunks.iter_mut().zip(samples)
+        {
+            let value = sample.to_f64();
+            count += clips(value) as u64;
+            *chunk = write(value);
+            chunk.reverse();
+        }
+    }
+    count
+}
+
+/// Decode samples which are stored exactly as `S`, without going through f64.
+fn native_decode<'a, S : Sample>(size : usize, bytes : &[u8], samples : impl Iterator<Item = &'a mut S>, little : bool)
+{
+    if little
+    {
+        for (chunk, sample) in bytes.chunks_exact(size).zip(samples) { *sample = S::decode(chunk); }
+    }
+    else
+    {
+        let mut swapped = [0; 8];
+        for (chunk, sample) in bytes.chunks_exact(size).zip(samples)
+        {
+            let swapped = &mut swapped[..size];
+            swapped.copy_from_slice(chunk);
+            swapped.reverse();
+            *sample = S::decode(swapped);
+        }
+    }
+}
+/// Encode samples which are stored exactly as `S`, which are always in range.
+fn native_encode<'a, S : Sample>(size : usize, samples : impl Iterator<Item = &'a S>, bytes : &mut [u8], little : bool)
+{
+    for (chunk, sample) in bytes.chunks_exact_mut(size).zip(samples)
+    {
+        sample.encode(chunk);
+        if !little { chunk.reverse(); }
+    }
+}
+
+fn fallback_decode<'a, S : Sample>(encoding : Encoding, bytes : &[u8], samples : impl Iterator<Item = &'a mut S>)
+{
+    for (chunk, sample) in bytes.chunks_exact(encoding.size()).zip(samples) { *sample = encoding.get(chunk); }
+}
+fn fallback_encode<'a, S : Sample>(encoding : Encoding, samples : impl Iterator<Item = &'a S>, bytes : &mut [u8]) -> Result<u64, usize>
+{
+    let mut count = 0;
+    for (index, (chunk, sample)) in bytes.chunks_exact_mut(encoding.size()).zip(samples).enumerate()
+    {
+        if !encoding.set(*sample, chunk) { continue; }
+        if encoding.clip == ClipPolicy::Error { return Err(index); }
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{sample, Justify};
+
+    fn int(bit_depth : u32, clip : ClipPolicy) -> Encoding
+    {
+        Encoding { format: SampleFormat::Int, bit_depth, container: bit_depth, justify: Justify::Left, endian: Endian::Little, clip }
+    }
+    /// Get samples in range, on both edges and beyond them.
+    fn values() -> Vec<f64> { [-2.5, -1.25, -1.0 - 1e-9, -1.0, -0.5, 0.0, 0.5, 1.0 - 1e-9, 1.0, 1.5, 3.75].to_vec() }
+
+    #[test]
+    fn encode_matches_int_clip()
+    {
+        for clip in [ClipPolicy::Saturate, ClipPolicy::Wrap, ClipPolicy::Error]
+        {
+            for bit_depth in [8, 16, 24, 32]
+            {
+                let (encoding, values) = (int(bit_depth, clip), values());
+                let mut bytes = vec![0; values.len() * bit_depth as usize / 8];
+                let result = encode(encoding, values.iter(), &mut bytes);
+                let expected : Vec<(i64, bool)> = values.iter().map(|value| sample::int_clip(*value, bit_depth, clip)).collect();
+                let first = expected.iter().position(|(_, clipped)| *clipped).unwrap_or(values.len());
+                let clips = expected.iter().filter(|(_, clipped)| *clipped).count() as u64;
+                assert_eq!(result, if clip == ClipPolicy::Error { Err(first) } else { Ok(clips) }, "{clip:?} {bit_depth}");
+                let stored = if clip == ClipPolicy::Error { first } else { values.len() };
+                for (index, (chunk, (value, _))) in bytes.chunks_exact(encoding.size()).zip(&expected).take(stored).enumerate()
+                {
+                    assert_eq!(encoding.get_int(chunk), *value, "{clip:?} {bit_depth} at {index}");
+                }
+            }
+        }
+    }
+    #[test]
+    fn wrap_is_twos_complement()
+    {
+        assert_eq!(sample::int_clip(1.5, 16, ClipPolicy::Wrap), (-16384, true));
+        assert_eq!(sample::int_clip(-1.25, 8, ClipPolicy::Wrap), (96, true));
+        assert_eq!(sample::int_clip(1.0, 24, ClipPolicy::Wrap), (-8388608, true));
+        assert_eq!(sample::int_clip(1.5, 16, ClipPolicy::Saturate), (32767, true));
+        assert_eq!(sample::int_clip(0.5, 16, ClipPolicy::Wrap), (16384, false));
+    }
+}
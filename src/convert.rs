@@ -0,0 +1,132 @@
+/// Target of a conversion into a new AudioBuffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Conversion
+{
+    format : crate::SampleFormat,
+    bit_depth : u32,
+    channels : u32,
+    speakers : crate::ChannelLayout,
+    layout : crate::Layout,
+    clip : crate::ClipPolicy,
+    dither : crate::Dither,
+    shaping : crate::NoiseShaping,
+    seed : u64
+}
+impl Conversion
+{
+    /// Create a conversion into interleaved samples which saturates without dither.
+    pub fn init(format : crate::SampleFormat, bit_depth : u32, channels : u32) -> Self
+    {
+        Self
+        {
+            format,
+            bit_depth,
+            channels,
+            speakers: crate::ChannelLayout::from_channels(channels),
+            layout: crate::Layout::Interleaved,
+            clip: crate::ClipPolicy::Saturate,
+            dither: crate::Dither::None,
+            shaping: crate::NoiseShaping::None,
+            seed: 0
+        }
+    }
+    /// Convert into the given channel layout and its channel count.
+    pub fn with_channel_layout(self, speakers : crate::ChannelLayout) -> Self { Self { channels: speakers.channels(), speakers, ..self } }
+    /// Convert into the given layout.
+    pub fn with_layout(self, layout : crate::Layout) -> Self { Self { layout, ..self } }
+    /// Apply the given clip policy.
+    pub fn with_clip_policy(self, clip : crate::ClipPolicy) -> Self { Self { clip, ..self } }
+    /// Apply the given dither and noise shaping.
+    pub fn with_dither(self, dither : crate::Dither, shaping : crate::NoiseShaping, seed : u64) -> Self { Self { dither, shaping, seed, ..self } }
+    /// Get a sample format.
+    pub fn format(&self) -> crate::SampleFormat { self.format }
+    /// Get a bit depth.
+    pub fn bit_depth(&self) -> u32 { self.bit_depth }
+    /// Get a channel count.
+    pub fn channels(&self) -> u32 { self.channels }
+    /// Get a channel layout.
+    pub fn channel_layout(&self) -> crate::ChannelLayout { self.speakers }
+    /// Get a layout.
+    pub fn layout(&self) -> crate::Layout { self.layout }
+    /// Get a clip policy.
+    pub fn clip_policy(&self) -> crate::ClipPolicy { self.clip }
+    /// Get a dither.
+    pub fn dither(&self) -> crate::Dither { self.dither }
+    /// Get a noise shaping filter.
+    pub fn noise_shaping(&self) -> crate::NoiseShaping { self.shaping }
+    /// Create an empty destination for the conversion.
+    pub(crate) fn destination(&self, frames : u32) -> crate::Result<crate::AudioBuffer>
+    {
+        let mut destination = crate::AudioBuffer::init_with_format(self.channels, self.bit_depth, frames, self.format)?;
+        destination.set_channel_layout(self.speakers)?;
+        destination.set_layout(self.layout);
+        destination.set_clip_policy(self.clip);
+        destination.set_dither(self.dither, self.shaping, self.seed);
+        Ok(destination)
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{AudioBuffer, ChannelLayout, ClipPolicy, Dither, Error, Layout, NoiseShaping, SampleFormat};
+
+    /// Create a 24-bit stereo buffer from interleaved samples.
+    fn stereo(samples : &[f64]) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init(2, 24, samples.len() as u32 / 2).unwrap();
+        buffer.write_slice(samples).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn stereo_to_planar_mono_averages()
+    {
+        let mut source = stereo(&[0.5, 0.25, -1.0, 0.5, 0.125, 0.125]);
+        source.set_sample_rate(Some(44100));
+        source.set_position(Some(96));
+        let mono = source.convert(&Conversion::init(SampleFormat::Int, 16, 1).with_layout(Layout::Planar)).unwrap();
+        assert_eq!((mono.format(), mono.bit_depth(), mono.channels(), mono.layout()), (SampleFormat::Int, 16, 1, Layout::Planar));
+        assert_eq!((mono.channel_layout(), mono.size()), (ChannelLayout::Mono, 3));
+        assert_eq!((mono.sample_rate(), mono.position()), (Some(44100), Some(96)));
+        assert_eq!(mono.channel_as::<i16>(0).unwrap(), [12288, -8192, 4096]);
+        let back = mono.convert(&Conversion::init(SampleFormat::Float, 32, 2)).unwrap();
+        // Mono spreads back over both sides with a constant power pan.
+        let expected = [0.375, 0.375, -0.25, -0.25, 0.125, 0.125].map(|sample| sample * std::f64::consts::FRAC_1_SQRT_2);
+        for (sample, expected) in back.samples().zip(expected) { assert!((sample - expected).abs() < 1e-6, "{sample} against {expected}"); }
+    }
+    #[test]
+    fn reduction_dithers_with_seed()
+    {
+        let samples : Vec<f64> = (0..512).map(|index| (index as f64 * 0.37 - 90.0) / 32768.0).collect();
+        let source = stereo(&samples);
+        let conversion = |seed| Conversion::init(SampleFormat::Int, 16, 2).with_dither(Dither::Tpdf, NoiseShaping::None, seed);
+        let (first, again, other) = (source.convert(&conversion(7)).unwrap(), source.convert(&conversion(7)).unwrap(), source.convert(&conversion(8)).unwrap());
+        assert_eq!(first.bytes(), again.bytes());
+        assert_ne!(first.bytes(), other.bytes());
+        // The conversion dithers like a direct write of the same samples with the same seed.
+        let mut direct = AudioBuffer::init(2, 16, 256).unwrap();
+        direct.set_dither(Dither::Tpdf, NoiseShaping::None, 7);
+        let mut stored = vec![0.0; 512];
+        source.read_slice(&mut stored).unwrap();
+        direct.write_slice(&stored).unwrap();
+        assert_eq!(first.bytes(), direct.bytes());
+        for (sample, value) in first.samples().zip(&stored) { assert!((sample - value).abs() * 32768.0 <= 1.5, "{value} became {sample}"); }
+    }
+    #[test]
+    fn error_policy_stops_at_first_rejected_sample()
+    {
+        let source : AudioBuffer = [0.5, 0.25, 1.5, 0.75, 2.0].into_iter().collect();
+        let mut destination = AudioBuffer::init(1, 16, 1).unwrap();
+        destination.set_clip_policy(ClipPolicy::Error);
+        assert!(matches!(source.convert_into(&mut destination), Err(Error::Clipped { channel: 0, frame: 2 })));
+        let mut read = [0.0; 5];
+        destination.read_slice(&mut read).unwrap();
+        assert_eq!(read, [0.5, 0.25, 0.0, 0.0, 0.0]);
+        assert_eq!(destination.clip_count(), 1);
+        let conversion = Conversion::init(SampleFormat::Int, 16, 1).with_clip_policy(ClipPolicy::Error);
+        assert!(matches!(source.convert(&conversion), Err(Error::Clipped { channel: 0, frame: 2 })));
+    }
+}
@@ -0,0 +1,224 @@
+//! Dither and noise shaping applied while quantizing into integer samples.
+
+/// Noise added before quantizing to decorrelate the rounding error from the signal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither
+{
+    /// Keep the plain truncation of the sample format.
+    #[default]
+    None,
+    /// Uniform noise of 1 LSB peak to peak.
+    Rectangular,
+    /// Triangular noise of 2 LSB peak to peak, the sum of two uniform values.
+    Tpdf,
+    /// Triangular noise made of the difference of consecutive uniform values, which pushes it to high frequencies.
+    HighPassTpdf
+}
+
+/// Error feedback filter which moves the quantization noise away from the most audible frequencies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoiseShaping
+{
+    /// Leave the noise spectrum flat.
+    #[default]
+    None,
+    /// First order high-pass, noise transfer `1 - z^-1`.
+    FirstOrder,
+    /// Second order high-pass, noise transfer `(1 - z^-1)^2`.
+    SecondOrder,
+    /// Five tap E-weighted filter by Lipshitz et al. designed for 44.1 kHz.
+    Lipshitz
+}
+impl NoiseShaping
+{
+    fn coefficients(self) -> &'static [f64]
+    {
+        match self
+        {
+            Self::None => &[],
+            Self::FirstOrder => &[1.0],
+            Self::SecondOrder => &[2.0, -1.0],
+            Self::Lipshitz => &[2.033, -2.165, 1.959, -1.590, 0.6149]
+        }
+    }
+}
+
+/// Dither settings with the state of every channel.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ditherer
+{
+    dither : Dither,
+    shaping : NoiseShaping,
+    seed : u64,
+    channels : Vec<ChannelState>
+}
+#[derive(Clone, Debug, Default)]
+struct ChannelState
+{
+    random : u64,
+    previous : f64,
+    errors : [f64; 5]
+}
+impl Ditherer
+{
+    pub(crate) fn init(dither : Dither, shaping : NoiseShaping, seed : u64) -> Self { Self { dither, shaping, seed, channels: Vec::new() } }
+    pub(crate) fn dither(&self) -> Dither { self.dither }
+    pub(crate) fn shaping(&self) -> NoiseShaping { self.shaping }
+    pub(crate) fn is_active(&self) -> bool { self.dither != Dither::None || self.shaping != NoiseShaping::None }
+    /// Restart the noise of every channel from the seed and clear the error history.
+    pub(crate) fn reset(&mut self) { self.channels.clear(); }
+    /// Round a normalized sample onto the grid of the given bit depth.
+    pub(crate) fn process(&mut self, channel : u32, value : f64, bit_depth : u32) -> f64
+    {
+        if !value.is_finite() { return value; }
+        let channel = channel as usize;
+        if self.channels.len() <= channel
+        {
+            let seed = self.seed;
+            self.channels.extend((self.channels.len()..=channel).map(|index| ChannelState { random: seed ^ mix(index as u64), ..ChannelState::default() }));
+        }
+        let state = &mut self.channels[channel];
+        let coefficients = self.shaping.coefficients();
+        let scale = (1_u64 << (bit_depth - 1)) as f64;
+        let target = value * scale - coefficients.iter().zip(&state.errors).map(|(coefficient, error)| coefficient * error).sum::<f64>();
+        let noise = match self.dither
+        {
+            Dither::None => 0.0,
+            Dither::Rectangular => state.uniform(),
+            Dither::Tpdf => state.uniform() + state.uniform(),
+            Dither::HighPassTpdf =>
+            {
+                let random = state.uniform();
+                let noise = random - state.previous;
+                state.previous = random;
+                noise
+            }
+        };
+        let quantized = (target + noise).round();
+        if !coefficients.is_empty()
+        {
+            state.errors.rotate_right(1);
+            state.errors[0] = quantized - target;
+        }
+        quantized / scale
+    }
+}
+impl ChannelState
+{
+    /// Uniform value in `[-0.5, 0.5)`.
+    fn uniform(&mut self) -> f64
+    {
+        self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        (mix(self.random) >> 11) as f64 / (1_u64 << 53) as f64 - 0.5
+    }
+}
+
+/// SplitMix64 finalizer.
+fn mix(value : u64) -> u64
+{
+    let value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{AudioBuffer, AudioWrite, ClipPolicy, Error};
+
+    /// Write a ramp of values between the 16-bit steps into a dithered buffer.
+    fn dithered(dither : Dither, shaping : NoiseShaping, seed : u64) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init(2, 16, 512).unwrap();
+        buffer.set_dither(dither, shaping, seed);
+        buffer.write_slice(&ramp(1024)).unwrap();
+        buffer
+    }
+    fn ramp(len : usize) -> Vec<f64> { (0..len).map(|index| (index as f64 * 0.37 - 150.0) / 32768.0).collect() }
+
+    #[test]
+    fn seed_reproduces_output()
+    {
+        for (dither, shaping) in [(Dither::Tpdf, NoiseShaping::None), (Dither::HighPassTpdf, NoiseShaping::Lipshitz), (Dither::Rectangular, NoiseShaping::SecondOrder)]
+        {
+            assert_eq!(dithered(dither, shaping, 7).bytes(), dithered(dither, shaping, 7).bytes(), "{dither:?} {shaping:?}");
+            assert_ne!(dithered(dither, shaping, 7).bytes(), dithered(dither, shaping, 8).bytes(), "{dither:?} {shaping:?}");
+        }
+    }
+    #[test]
+    fn reset_restarts_noise()
+    {
+        let mut buffer = dithered(Dither::Tpdf, NoiseShaping::FirstOrder, 3);
+        let first = buffer.bytes().to_vec();
+        buffer.write_slice(&ramp(1024)).unwrap();
+        assert_ne!(buffer.bytes(), &first[..]);
+        buffer.reset_dither();
+        buffer.write_slice(&ramp(1024)).unwrap();
+        assert_eq!(buffer.bytes(), &first[..]);
+    }
+    #[test]
+    fn noise_stays_within_two_steps()
+    {
+        let buffer = dithered(Dither::Tpdf, NoiseShaping::None, 11);
+        let mut read = vec![0.0; 1024];
+        buffer.read_slice(&mut read).unwrap();
+        for (value, sample) in ramp(1024).iter().zip(&read) { assert!((value - sample).abs() * 32768.0 <= 1.5, "{value} became {sample}"); }
+    }
+    #[test]
+    fn bit_depth_reduction_dithers()
+    {
+        // A constant between two 16-bit steps truncates to one step without dither but averages out with it.
+        let value = 0.3 / 32768.0;
+        let mut plain = AudioBuffer::init(1, 24, 4096).unwrap();
+        plain.write_slice(&vec![value; 4096]).unwrap();
+        let mut dithered = plain.clone();
+        plain.set_bit_depth(16).unwrap();
+        dithered.set_dither(Dither::Tpdf, NoiseShaping::None, 5);
+        dithered.set_bit_depth(16).unwrap();
+        assert_eq!((dithered.bit_depth(), dithered.container()), (16, 16));
+        let mean = |buffer : &AudioBuffer| buffer.samples().sum::<f64>() / 4096.0 * 32768.0;
+        assert_eq!(mean(&plain), 0.0);
+        assert!((mean(&dithered) - 0.3).abs() < 0.05, "mean {}", mean(&dithered));
+    }
+    #[test]
+    fn rejected_dithered_write_stops_at_the_clip()
+    {
+        let mut buffer = AudioBuffer::init(1, 16, 256).unwrap();
+        buffer.set_dither(Dither::Tpdf, NoiseShaping::None, 9);
+        buffer.set_clip_policy(ClipPolicy::Error);
+        // Passes the plain range check, but the noise pushes some samples over full scale.
+        let loud = vec![32767.0 / 32768.0; 256];
+        let Err(Error::Clipped { channel: 0, frame }) = buffer.write_slice(&loud) else { panic!("write was not rejected") };
+        assert!(frame > 0);
+        assert!(buffer.samples().take(frame as usize).all(|sample| sample > 0.99));
+        assert!(buffer.samples().skip(frame as usize).all(|sample| sample == 0.0));
+        assert_eq!(buffer.clip_count(), 1);
+        // The same noise stops a fresh buffer at the same sample.
+        let mut fresh = AudioBuffer::init(1, 16, 256).unwrap();
+        fresh.set_dither(Dither::Tpdf, NoiseShaping::None, 9);
+        fresh.set_clip_policy(ClipPolicy::Error);
+        let mut view = fresh.view_mut(0..256, 0..1).unwrap();
+        assert!(matches!(view.write_slice(&loud), Err(Error::Clipped { channel: 0, frame: at }) if at == frame));
+        assert_eq!(buffer.bytes(), fresh.bytes());
+    }
+    #[test]
+    fn rejected_bit_depth_reduction_changes_nothing()
+    {
+        let mut buffer = AudioBuffer::init(1, 24, 256).unwrap();
+        let loud = vec![8388607.0 / 8388608.0; 256];
+        buffer.write_slice(&loud).unwrap();
+        buffer.set_dither(Dither::Tpdf, NoiseShaping::None, 9);
+        buffer.set_clip_policy(ClipPolicy::Error);
+        assert!(matches!(buffer.set_bit_depth(16), Err(Error::Clipped { .. })));
+        assert_eq!((buffer.bit_depth(), buffer.container(), buffer.clip_count()), (24, 24, 1));
+        let mut read = vec![0.0; 256];
+        buffer.read_slice(&mut read).unwrap();
+        assert_eq!(read, loud);
+        // Without the error policy the same reduction goes through, saturating the noise at full scale.
+        buffer.set_clip_policy(ClipPolicy::Saturate);
+        buffer.set_bit_depth(16).unwrap();
+        assert_eq!(buffer.bit_depth(), 16);
+        assert!(buffer.samples().all(|sample| sample > 0.99));
+    }
+}
@@ -0,0 +1,181 @@
+//! Fade curves and gain envelopes over stream positions.
+
+/// Shape of a gain transition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve
+{
+    /// Gain moves at a constant rate, so the gains of a crossfade sum to one.
+    #[default]
+    Linear,
+    /// Power moves at a constant rate along a quarter sine, so the powers of a crossfade sum to one.
+    EqualPower
+}
+impl FadeCurve
+{
+    /// Get the gain at a point from 0 to 1 of a transition between two non-negative gains.
+    pub fn interpolate(self, from : f64, to : f64, position : f64) -> f64
+    {
+        let position = position.clamp(0.0, 1.0);
+        match self
+        {
+            Self::Linear => from + (to - from) * position,
+            Self::EqualPower =>
+            {
+                let (to_part, from_part) = (position * std::f64::consts::FRAC_PI_2).sin_cos();
+                ((from * from_part).powi(2) + (to * to_part).powi(2)).sqrt()
+            }
+        }
+    }
+}
+
+/// Gain over the frames of a stream, moving between breakpoints.
+///
+/// Each breakpoint holds its gain from its frame on and is reached along its curve from the previous breakpoint. The
+/// gain before the first breakpoint is the gain of the first one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainEnvelope
+{
+    points : Vec<(u64, f64, FadeCurve)>
+}
+impl Default for GainEnvelope
+{
+    fn default() -> Self { Self::init(1.0) }
+}
+impl GainEnvelope
+{
+    /// Create a constant gain.
+    pub fn init(gain : f64) -> Self { Self { points: vec![(0, gain, FadeCurve::Linear)] } }
+    /// Reach the gain at the frame along the curve, replacing a breakpoint at the same frame.
+    pub fn set_point(&mut self, frame : u64, gain : f64, curve : FadeCurve)
+    {
+        match self.points.binary_search_by_key(&frame, |point| point.0)
+        {
+            Ok(index) => self.points[index] = (frame, gain, curve),
+            Err(index) => self.points.insert(index, (frame, gain, curve))
+        }
+    }
+    /// Hold the current gain until the start frame and move to the gain over the following frames.
+    ///
+    /// Breakpoints from the start frame on are replaced.
+    pub fn ramp(&mut self, start : u64, frames : u64, gain : f64, curve : FadeCurve)
+    {
+        let held = self.gain_at(start);
+        self.points.retain(|point| point.0 < start);
+        self.set_point(start, held, FadeCurve::Linear);
+        self.set_point(start + frames, gain, curve);
+    }
+    /// Get the gain at a frame.
+    pub fn gain_at(&self, frame : u64) -> f64
+    {
+        let index = self.points.partition_point(|point| point.0 <= frame);
+        match (index.checked_sub(1).map(|index| self.points[index]), self.points.get(index))
+        {
+            (Some((start, from, _)), Some(&(end, to, curve))) => curve.interpolate(from, to, (frame - start) as f64 / (end - start) as f64),
+            (Some((_, gain, _)), None) | (None, Some(&(_, gain, _))) => gain,
+            (None, None) => 1.0
+        }
+    }
+    /// Iterate over the frame, gain and curve of every breakpoint.
+    pub fn points(&self) -> impl Iterator<Item = (u64, f64, FadeCurve)> + '_ { self.points.iter().copied() }
+    /// Check whether the gain stays the same from the frame on.
+    pub fn is_constant_from(&self, frame : u64) -> bool
+    {
+        let gain = self.gain_at(frame);
+        self.points.iter().all(|point| point.0 <= frame || point.1 == gain)
+    }
+    /// Drop the breakpoints which no longer affect the gain from the frame on, without allocating.
+    pub fn discard_before(&mut self, frame : u64)
+    {
+        let index = self.points.partition_point(|point| point.0 <= frame);
+        if index > 1 { self.points.drain(..index - 1); }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{AudioBuffer, AudioWrite, SampleFormat};
+
+    /// Create a float buffer holding a constant in every sample.
+    fn constant(channels : u32, frames : u32, value : f64) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init_with_format(channels, 64, frames, SampleFormat::Float).unwrap();
+        buffer.write_slice(&vec![value; (channels * frames) as usize]).unwrap();
+        buffer
+    }
+    fn close(left : f64, right : f64) -> bool { (left - right).abs() < 1e-12 }
+
+    #[test]
+    fn fades_follow_their_curve()
+    {
+        let mut linear = constant(1, 8, 1.0);
+        linear.fade_in(4, FadeCurve::Linear).unwrap();
+        assert_eq!(linear.samples().collect::<Vec<_>>(), [0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0]);
+        linear.fade_out(4, FadeCurve::Linear).unwrap();
+        assert_eq!(linear.samples().collect::<Vec<_>>(), [0.0, 0.25, 0.5, 0.75, 0.75, 0.5, 0.25, 0.0]);
+        let mut power = constant(1, 8, 1.0);
+        power.fade_in(8, FadeCurve::EqualPower).unwrap();
+        for (frame, sample) in power.samples().enumerate() { assert!(close(sample, (frame as f64 / 8.0 * std::f64::consts::FRAC_PI_2).sin()), "frame {frame}"); }
+        let mut power = constant(1, 8, 1.0);
+        power.fade_out(8, FadeCurve::EqualPower).unwrap();
+        for (frame, sample) in power.samples().enumerate() { assert!(close(sample, ((frame + 1) as f64 / 8.0 * std::f64::consts::FRAC_PI_2).cos()), "frame {frame}"); }
+        // Fades longer than the buffer cover the whole buffer.
+        let mut short = constant(1, 2, 1.0);
+        short.fade_in(100, FadeCurve::Linear).unwrap();
+        assert_eq!(short.samples().collect::<Vec<_>>(), [0.0, 0.5]);
+    }
+    #[test]
+    fn crossfades_keep_gain_or_power()
+    {
+        let mut linear = constant(2, 16, 0.5);
+        linear.crossfade(&constant(2, 16, 0.5), FadeCurve::Linear).unwrap();
+        assert!(linear.samples().all(|sample| close(sample, 0.5)));
+        // Outgoing left and incoming right keep apart, so their powers show the gains of both sides.
+        let mut outgoing = constant(2, 16, 0.5);
+        outgoing.write_channel(1, &[0.0; 16]).unwrap();
+        let mut incoming = constant(2, 16, 0.5);
+        incoming.write_channel(0, &[0.0; 16]).unwrap();
+        outgoing.crossfade(&incoming.view(0..16, 0..2).unwrap(), FadeCurve::EqualPower).unwrap();
+        for frame in 0..16
+        {
+            let (out, into) = (outgoing.read_sample(0, frame) / 0.5, outgoing.read_sample(1, frame) / 0.5);
+            assert!(close(out * out + into * into, 1.0), "frame {frame}");
+        }
+        assert_eq!((outgoing.read_sample(0, 0), outgoing.read_sample(1, 0)), (0.5, 0.0));
+    }
+    #[test]
+    fn envelope_gains_between_points()
+    {
+        let mut envelope = GainEnvelope::init(0.5);
+        envelope.set_point(100, 1.0, FadeCurve::Linear);
+        envelope.set_point(200, 0.0, FadeCurve::EqualPower);
+        assert_eq!((envelope.gain_at(0), envelope.gain_at(50), envelope.gain_at(100)), (0.5, 0.75, 1.0));
+        assert!(close(envelope.gain_at(150), std::f64::consts::FRAC_1_SQRT_2));
+        assert_eq!((envelope.gain_at(200), envelope.gain_at(u64::MAX)), (0.0, 0.0));
+        assert!(!envelope.is_constant_from(150) && envelope.is_constant_from(200));
+        // Only the breakpoint before the current one is dropped, so the gain from the frame on stays the same.
+        let (held, fading) = (envelope.gain_at(100), envelope.gain_at(150));
+        envelope.discard_before(150);
+        assert_eq!(envelope.points().map(|point| point.0).collect::<Vec<_>>(), [100, 200]);
+        assert_eq!((envelope.gain_at(100), envelope.gain_at(150)), (held, fading));
+        envelope.discard_before(250);
+        assert_eq!(envelope.points().collect::<Vec<_>>(), [(200, 0.0, FadeCurve::EqualPower)]);
+        assert_eq!(envelope.gain_at(0), 0.0);
+        let mut ramp = GainEnvelope::default();
+        ramp.ramp(10, 10, 0.0, FadeCurve::Linear);
+        assert_eq!((ramp.gain_at(10), ramp.gain_at(15), ramp.gain_at(20)), (1.0, 0.5, 0.0));
+    }
+    #[test]
+    fn envelopes_start_at_the_position()
+    {
+        let mut envelope = GainEnvelope::init(1.0);
+        envelope.ramp(100, 4, 0.0, FadeCurve::Linear);
+        let mut buffer = constant(1, 8, 1.0);
+        buffer.set_position(Some(98));
+        buffer.view_mut(4..8, 0..1).unwrap().apply_envelope(&envelope).unwrap();
+        assert_eq!(buffer.samples().collect::<Vec<_>>(), [1.0, 1.0, 1.0, 1.0, 0.5, 0.25, 0.0, 0.0]);
+        buffer.apply_envelope(&envelope).unwrap();
+        assert_eq!(buffer.samples().collect::<Vec<_>>(), [1.0, 1.0, 1.0, 0.75, 0.25, 0.0625, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,80 @@
+/// Error returned by fallible operations of the crate.
+#[derive(Debug)]
+pub enum Error
+{
+    /// The bit depth cannot be stored in the sample format.
+    InvalidBitDepth { format : crate::SampleFormat, bit_depth : u32 },
+    /// The container cannot hold the samples of the buffer.
+    InvalidContainer { format : crate::SampleFormat, bit_depth : u32, container : u32 },
+    /// The buffer would not fit into memory.
+    TooLarge { channels : u32, frames : u32, container : u32 },
+    /// A given slice does not have the expected length.
+    LengthMismatch { expected : usize, actual : usize },
+    /// The range does not fit into the buffer.
+    RangeOutOfBounds { start : u32, end : u32, len : u32 },
+    /// The channel does not exist in the buffer.
+    ChannelOutOfRange { channel : u32, channels : u32 },
+    /// The channel layout does not have the channel count of the buffer.
+    InvalidChannelLayout { layout : crate::ChannelLayout, channels : u32 },
+    /// The buffers of one program do not have the same channel count.
+    ChannelMismatch { expected : u32, actual : u32 },
+    /// The mixing matrix does not have the channel counts of the buffers.
+    MatrixMismatch { inputs : u32, outputs : u32, from : u32, to : u32 },
+    /// The sample is out of range and the clip policy rejects it.
+    Clipped { channel : u32, frame : u32 },
+    /// The sample rate is not a multiple of 22050 or 24000 Hz.
+    InvalidSampleRate(u32),
+    /// The device stream cannot carry the channel count, which must be 1 to 64.
+    InvalidChannelCount(u32),
+    /// The device stream sent an unknown sample format code.
+    InvalidFormatCode(u8),
+    /// The device stream sent a payload that does not divide into whole samples.
+    InvalidPayload { len : usize, samples : u32 },
+    /// The WAVE file is malformed or uses an unsupported encoding.
+    InvalidWave(&'static str),
+    /// The underlying reader or writer failed.
+    Io(std::io::Error)
+}
+impl std::fmt::Display for Error
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Self::InvalidBitDepth { format, bit_depth } => write!(f, "{bit_depth}-bit samples cannot be stored as {format:?}"),
+            Self::InvalidContainer { format, bit_depth, container } => write!(f, "{bit_depth}-bit {format:?} samples cannot be stored in a {container}-bit container"),
+            Self::TooLarge { channels, frames, container } => write!(f, "{frames} frames of {channels} channels in {container}-bit containers do not fit into memory"),
+            Self::LengthMismatch { expected, actual } => write!(f, "expected a slice of {expected} samples but got {actual}"),
+            Self::RangeOutOfBounds { start, end, len } => write!(f, "range {start}..{end} does not fit into {len}"),
+            Self::ChannelOutOfRange { channel, channels } => write!(f, "channel {channel} does not exist in a buffer of {channels} channels"),
+            Self::InvalidChannelLayout { layout, channels } => write!(f, "{layout:?} layout does not fit a buffer of {channels} channels"),
+            Self::ChannelMismatch { expected, actual } => write!(f, "expected a buffer of {expected} channels but got {actual}"),
+            Self::MatrixMismatch { inputs, outputs, from, to } => write!(f, "matrix of {inputs} inputs and {outputs} outputs cannot mix {from} channels into {to}"),
+            Self::Clipped { channel, frame } => write!(f, "sample of channel {channel} in frame {frame} is out of range"),
+            Self::InvalidSampleRate(sample_rate) => write!(f, "invalid sample rate {sample_rate}, expected a multiple of 22050 or 24000 Hz"),
+            Self::InvalidChannelCount(channels) => write!(f, "invalid channel count {channels}, expected 1 to 64"),
+            Self::InvalidFormatCode(code) => write!(f, "unknown sample format code {code}"),
+            Self::InvalidPayload { len, samples } => write!(f, "payload of {len} bytes does not divide into {samples} samples"),
+            Self::InvalidWave(reason) => write!(f, "invalid WAVE file: {reason}"),
+            Self::Io(error) => write!(f, "{error}")
+        }
+    }
+}
+impl std::error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            Self::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+impl From<std::io::Error> for Error
+{
+    fn from(error : std::io::Error) -> Self { Self::Io(error) }
+}
+
+/// Result of fallible operations of the crate.
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,118 @@
+//! ITU-T G.711 companding between 8-bit codes and 16-bit linear samples.
+
+const SEGMENT_A : [i16; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+const SEGMENT_MU : [i16; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+const BIAS : i16 = 0x84;
+const CLIP : i16 = 8159;
+
+fn segment(value : i16, table : &[i16; 8]) -> u8 { table.iter().position(|end| value <= *end).unwrap_or(8) as u8 }
+
+/// Compress a linear sample into an A-law code.
+pub(crate) fn alaw_encode(sample : i16) -> u8
+{
+    let mut value = sample >> 3;
+    let mask = if value < 0
+    {
+        value = -value - 1;
+        0x55
+    }
+    else { 0xD5 };
+    let segment = segment(value, &SEGMENT_A);
+    if segment >= 8 { return 0x7F ^ mask; }
+    let shift = if segment < 2 { 1 } else { segment };
+    ((segment << 4) | ((value >> shift) & 0x0F) as u8) ^ mask
+}
+/// Expand an A-law code into a linear sample.
+pub(crate) fn alaw_decode(code : u8) -> i16
+{
+    let code = code ^ 0x55;
+    let mut value = ((code & 0x0F) as i16) << 4;
+    let segment = (code & 0x70) >> 4;
+    match segment
+    {
+        0 => value += 8,
+        1 => value += 0x108,
+        _ => value = (value + 0x108) << (segment - 1)
+    }
+    if code & 0x80 != 0 { value } else { -value }
+}
+/// Compress a linear sample into a mu-law code.
+pub(crate) fn mulaw_encode(sample : i16) -> u8
+{
+    let mut value = sample >> 2;
+    let mask = if value < 0
+    {
+        value = -value;
+        0x7F
+    }
+    else { 0xFF };
+    value = value.min(CLIP) + (BIAS >> 2);
+    let segment = segment(value, &SEGMENT_MU);
+    if segment >= 8 { return 0x7F ^ mask; }
+    ((segment << 4) | ((value >> (segment + 1)) & 0x0F) as u8) ^ mask
+}
+/// Expand a mu-law code into a linear sample.
+pub(crate) fn mulaw_decode(code : u8) -> i16
+{
+    let code = !code;
+    let value = ((((code & 0x0F) as i16) << 3) + BIAS) << ((code & 0x70) >> 4);
+    if code & 0x80 != 0 { BIAS - value } else { value - BIAS }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn alaw_known_codes()
+    {
+        assert_eq!(alaw_encode(0), 0xD5);
+        assert_eq!(alaw_encode(-1), 0x55);
+        assert_eq!(alaw_encode(i16::MAX), 0xAA);
+        assert_eq!(alaw_encode(i16::MIN), 0x2A);
+        assert_eq!(alaw_decode(0xD5), 8);
+        assert_eq!(alaw_decode(0x55), -8);
+        assert_eq!(alaw_decode(0xAA), 32256);
+        assert_eq!(alaw_decode(0x2A), -32256);
+    }
+    #[test]
+    fn mulaw_known_codes()
+    {
+        assert_eq!(mulaw_encode(0), 0xFF);
+        assert_eq!(mulaw_encode(-4), 0x7E);
+        assert_eq!(mulaw_decode(0x7E), -8);
+        assert_eq!(mulaw_encode(i16::MAX), 0x80);
+        assert_eq!(mulaw_encode(i16::MIN), 0x00);
+        assert_eq!(mulaw_decode(0xFF), 0);
+        assert_eq!(mulaw_decode(0x7F), 0);
+        assert_eq!(mulaw_decode(0x80), 32124);
+        assert_eq!(mulaw_decode(0x00), -32124);
+    }
+    #[test]
+    fn segment_ends()
+    {
+        let alaw : Vec<i16> = (0..8).map(|segment| alaw_decode(0xD5 ^ (segment << 4 | 0x0F))).collect();
+        assert_eq!(alaw, [248, 504, 1008, 2016, 4032, 8064, 16128, 32256]);
+        let mulaw : Vec<i16> = (0..8).map(|segment| mulaw_decode(!(segment << 4 | 0x0F))).collect();
+        assert_eq!(mulaw, [120, 372, 876, 1884, 3900, 7932, 15996, 32124]);
+    }
+    #[test]
+    fn codes_round_trip()
+    {
+        for code in 0..=u8::MAX
+        {
+            assert_eq!(alaw_encode(alaw_decode(code)), code, "A-law code {code:#04X}");
+            // Negative zero decodes to 0, which encodes as positive zero.
+            if code != 0x7F { assert_eq!(mulaw_encode(mulaw_decode(code)), code, "mu-law code {code:#04X}"); }
+        }
+    }
+    #[test]
+    fn decoding_is_monotonic()
+    {
+        let alaw : Vec<i16> = (0..=u8::MAX).map(|code| alaw_decode(code ^ 0xD5)).collect();
+        let mulaw : Vec<i16> = (0..=u8::MAX).map(|code| mulaw_decode(!code)).collect();
+        assert!(alaw[..128].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(mulaw[..128].windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
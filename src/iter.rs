@@ -0,0 +1,532 @@
+/// Iterator over the samples of a source in interleaved order.
+pub struct Samples<'a, A : ?Sized>
+{
+    source : &'a A,
+    index : usize,
+    end : usize
+}
+impl<'a, A : crate::AudioRead + ?Sized> Samples<'a, A>
+{
+    pub(crate) fn init(source : &'a A) -> Self { Self { source, index: 0, end: source.size() as usize * source.channels() as usize } }
+    fn read(&self, index : usize) -> f64
+    {
+        let channels = self.source.channels() as usize;
+        self.source.read_sample((index % channels) as u32, (index / channels) as u32)
+    }
+}
+impl<A : crate::AudioRead + ?Sized> Iterator for Samples<'_, A>
+{
+    type Item = f64;
+    fn next(&mut self) -> Option<f64>
+    {
+        if self.index == self.end { return None; }
+        self.index += 1;
+        Some(self.read(self.index - 1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.end - self.index, Some(self.end - self.index)) }
+}
+impl<A : crate::AudioRead + ?Sized> DoubleEndedIterator for Samples<'_, A>
+{
+    fn next_back(&mut self) -> Option<f64>
+    {
+        if self.index == self.end { return None; }
+        self.end -= 1;
+        Some(self.read(self.end))
+    }
+}
+impl<A : crate::AudioRead + ?Sized> ExactSizeIterator for Samples<'_, A> { }
+
+/// Iterator over the frames of a source.
+pub struct Frames<'a, A : ?Sized>
+{
+    source : &'a A,
+    frame : u32,
+    end : u32
+}
+impl<'a, A : crate::AudioRead + ?Sized> Frames<'a, A>
+{
+    pub(crate) fn init(source : &'a A) -> Self { Self { source, frame: 0, end: source.size() } }
+}
+impl<'a, A : crate::AudioRead + ?Sized> Iterator for Frames<'a, A>
+{
+    type Item = Frame<'a, A>;
+    fn next(&mut self) -> Option<Frame<'a, A>>
+    {
+        if self.frame == self.end { return None; }
+        self.frame += 1;
+        Some(Frame { source: self.source, frame: self.frame - 1 })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { ((self.end - self.frame) as usize, Some((self.end - self.frame) as usize)) }
+}
+impl<'a, A : crate::AudioRead + ?Sized> DoubleEndedIterator for Frames<'a, A>
+{
+    fn next_back(&mut self) -> Option<Frame<'a, A>>
+    {
+        if self.frame == self.end { return None; }
+        self.end -= 1;
+        Some(Frame { source: self.source, frame: self.end })
+    }
+}
+impl<A : crate::AudioRead + ?Sized> ExactSizeIterator for Frames<'_, A> { }
+
+/// Single frame of a source with one sample per channel.
+pub struct Frame<'a, A : ?Sized>
+{
+    source : &'a A,
+    frame : u32
+}
+impl<'a, A : crate::AudioRead + ?Sized> Frame<'a, A>
+{
+    /// Get an index of the frame.
+    pub fn index(&self) -> u32 { self.frame }
+    /// Get a channel count.
+    pub fn len(&self) -> u32 { self.source.channels() }
+    /// Check whether the frame has no channels.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Read the sample of the channel.
+    pub fn get(&self, channel : u32) -> f64 { self.source.read_sample(channel, self.frame) }
+    /// Iterate over the samples of every channel.
+    pub fn iter(&self) -> FrameIter<'a, A> { FrameIter { source: self.source, frame: self.frame, channel: 0, end: self.len() } }
+}
+impl<A : ?Sized> Clone for Frame<'_, A>
+{
+    fn clone(&self) -> Self { *self }
+}
+impl<A : ?Sized> Copy for Frame<'_, A> { }
+
+/// Iterator over the samples of a frame.
+pub struct FrameIter<'a, A : ?Sized>
+{
+    source : &'a A,
+    frame : u32,
+    channel : u32,
+    end : u32
+}
+impl<A : crate::AudioRead + ?Sized> Iterator for FrameIter<'_, A>
+{
+    type Item = f64;
+    fn next(&mut self) -> Option<f64>
+    {
+        if self.channel == self.end { return None; }
+        self.channel += 1;
+        Some(self.source.read_sample(self.channel - 1, self.frame))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { ((self.end - self.channel) as usize, Some((self.end - self.channel) as usize)) }
+}
+impl<A : crate::AudioRead + ?Sized> ExactSizeIterator for FrameIter<'_, A> { }
+
+/// Single channel of a source.
+pub struct Channel<'a, A : ?Sized>
+{
+    source : &'a A,
+    channel : u32
+}
+impl<'a, A : crate::AudioRead + ?Sized> Channel<'a, A>
+{
+    pub(crate) fn init(source : &'a A, channel : u32) -> Self
+    {
+        assert!(channel < source.channels(), "Channel is out of range.");
+        Self { source, channel }
+    }
+    /// Get an index of the channel.
+    pub fn index(&self) -> u32 { self.channel }
+    /// Get a buffer size in frames.
+    pub fn len(&self) -> u32 { self.source.size() }
+    /// Check whether the channel has no frames.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Read the sample of the frame.
+    pub fn get(&self, frame : u32) -> f64 { self.source.read_sample(self.channel, frame) }
+    /// Iterate over the samples of every frame.
+    pub fn iter(&self) -> ChannelIter<'a, A> { ChannelIter { source: self.source, channel: self.channel, frame: 0, end: self.len() } }
+}
+impl<A : ?Sized> Clone for Channel<'_, A>
+{
+    fn clone(&self) -> Self { *self }
+}
+impl<A : ?Sized> Copy for Channel<'_, A> { }
+
+/// Iterator over the samples of a channel.
+pub struct ChannelIter<'a, A : ?Sized>
+{
+    source : &'a A,
+    channel : u32,
+    frame : u32,
+    end : u32
+}
+impl<A : crate::AudioRead + ?Sized> Iterator for ChannelIter<'_, A>
+{
+    type Item = f64;
+    fn next(&mut self) -> Option<f64>
+    {
+        if self.frame == self.end { return None; }
+        self.frame += 1;
+        Some(self.source.read_sample(self.channel, self.frame - 1))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { ((self.end - self.frame) as usize, Some((self.end - self.frame) as usize)) }
+}
+impl<A : crate::AudioRead + ?Sized> DoubleEndedIterator for ChannelIter<'_, A>
+{
+    fn next_back(&mut self) -> Option<f64>
+    {
+        if self.frame == self.end { return None; }
+        self.end -= 1;
+        Some(self.source.read_sample(self.channel, self.end))
+    }
+}
+impl<A : crate::AudioRead + ?Sized> ExactSizeIterator for ChannelIter<'_, A> { }
+
+/// Mutable handle to a single stored sample.
+pub struct SampleMut<'a>
+{
+    bytes : &'a mut [u8],
+    encoding : crate::Encoding,
+    clips : &'a std::cell::Cell<u64>
+}
+impl SampleMut<'_>
+{
+    /// Read the sample.
+    pub fn get(&self) -> f64 { self.encoding.get(self.bytes) }
+    /// Write the sample, following the clip policy of the buffer.
+    pub fn set(&mut self, data : f64) { self.set_as(data); }
+    /// Read the sample as a native sample.
+    pub fn get_as<S : crate::Sample>(&self) -> S { self.encoding.get(self.bytes) }
+    /// Write the sample from a native sample, following the clip policy of the buffer.
+    pub fn set_as<S : crate::Sample>(&mut self, data : S)
+    {
+        if self.encoding.set(data, self.bytes) { self.clips.set(self.clips.get() + 1); }
+    }
+}
+
+/// Mutable iterator over the samples of an AudioBuffer in interleaved order.
+pub struct SamplesMut<'a>
+{
+    lanes : Lanes<'a>,
+    encoding : crate::Encoding,
+    remaining : usize,
+    clips : &'a std::cell::Cell<u64>
+}
+enum Lanes<'a>
+{
+    Interleaved(std::slice::ChunksExactMut<'a, u8>),
+    Planar(Vec<std::slice::ChunksExactMut<'a, u8>>, usize)
+}
+impl<'a> SamplesMut<'a>
+{
+    pub(crate) fn init(bytes : &'a mut [u8], encoding : crate::Encoding, layout : crate::Layout, channels : usize, frames : usize, clips : &'a std::cell::Cell<u64>) -> Self
+    {
+        let size = encoding.size();
+        let lanes = match layout
+        {
+            crate::Layout::Interleaved => Lanes::Interleaved(bytes.chunks_exact_mut(size.max(1))),
+            crate::Layout::Planar => Lanes::Planar(bytes.chunks_exact_mut((size * frames).max(1)).map(|channel| channel.chunks_exact_mut(size.max(1))).collect(), 0)
+        };
+        // Buffers without a sample format yet have no stored samples.
+        Self { lanes, encoding, remaining: if size == 0 { 0 } else { channels * frames }, clips }
+    }
+}
+impl<'a> Iterator for SamplesMut<'a>
+{
+    type Item = SampleMut<'a>;
+    fn next(&mut self) -> Option<SampleMut<'a>>
+    {
+        if self.remaining == 0 { return None; }
+        let bytes = match &mut self.lanes
+        {
+            Lanes::Interleaved(chunks) => chunks.next()?,
+            Lanes::Planar(channels, lane) =>
+            {
+                let bytes = channels[*lane].next()?;
+                *lane = (*lane + 1) % channels.len();
+                bytes
+            }
+        };
+        self.remaining -= 1;
+        Some(SampleMut { bytes, encoding: self.encoding, clips: self.clips })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.remaining, Some(self.remaining)) }
+}
+impl ExactSizeIterator for SamplesMut<'_> { }
+
+/// Mutable iterator over the frames of an AudioBuffer.
+pub struct FramesMut<'a>
+{
+    lanes : Lanes<'a>,
+    encoding : crate::Encoding,
+    remaining : usize,
+    clips : &'a std::cell::Cell<u64>
+}
+impl<'a> FramesMut<'a>
+{
+    pub(crate) fn init(bytes : &'a mut [u8], encoding : crate::Encoding, layout : crate::Layout, channels : usize, frames : usize, clips : &'a std::cell::Cell<u64>) -> Self
+    {
+        let size = encoding.size();
+        let lanes = match layout
+        {
+            crate::Layout::Interleaved => Lanes::Interleaved(bytes.chunks_exact_mut((size * channels).max(1))),
+            crate::Layout::Planar => Lanes::Planar(bytes.chunks_exact_mut((size * frames).max(1)).map(|channel| channel.chunks_exact_mut(size.max(1))).collect(), 0)
+        };
+        Self { lanes, encoding, remaining: if channels == 0 || size == 0 { 0 } else { frames }, clips }
+    }
+}
+impl<'a> Iterator for FramesMut<'a>
+{
+    type Item = FrameMut<'a>;
+    fn next(&mut self) -> Option<FrameMut<'a>>
+    {
+        if self.remaining == 0 { return None; }
+        let samples = match &mut self.lanes
+        {
+            Lanes::Interleaved(chunks) => FrameBytes::Contiguous(chunks.next()?),
+            Lanes::Planar(channels, _) => FrameBytes::Scattered(channels.iter_mut().map(|channel| channel.next()).collect::<Option<_>>()?)
+        };
+        self.remaining -= 1;
+        Some(FrameMut { samples, encoding: self.encoding, clips: self.clips })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.remaining, Some(self.remaining)) }
+}
+impl ExactSizeIterator for FramesMut<'_> { }
+
+/// Mutable handle to a single frame of an AudioBuffer.
+///
+/// Frames of planar buffers gather their samples from every channel, which allocates once per frame.
+pub struct FrameMut<'a>
+{
+    samples : FrameBytes<'a>,
+    encoding : crate::Encoding,
+    clips : &'a std::cell::Cell<u64>
+}
+enum FrameBytes<'a>
+{
+    Contiguous(&'a mut [u8]),
+    Scattered(Vec<&'a mut [u8]>)
+}
+impl<'a> FrameMut<'a>
+{
+    /// Get a channel count.
+    pub fn len(&self) -> u32
+    {
+        match &self.samples
+        {
+            FrameBytes::Contiguous(bytes) => (bytes.len() / self.encoding.size()) as u32,
+            FrameBytes::Scattered(samples) => samples.len() as u32
+        }
+    }
+    /// Check whether the frame has no channels.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Read the sample of the channel.
+    pub fn get(&self, channel : u32) -> f64 { self.encoding.get(self.bytes(channel)) }
+    /// Write the sample of the channel, following the clip policy of the buffer.
+    pub fn set(&mut self, channel : u32, data : f64)
+    {
+        let encoding = self.encoding;
+        if encoding.set(data, self.bytes_mut(channel)) { self.clips.set(self.clips.get() + 1); }
+    }
+    /// Iterate over the samples of every channel.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = SampleMut<'_>> + use<'_, 'a>
+    {
+        let (encoding, size, clips) = (self.encoding, self.encoding.size(), self.clips);
+        let (contiguous, scattered) = match &mut self.samples
+        {
+            FrameBytes::Contiguous(bytes) => (Some(bytes.chunks_exact_mut(size)), None),
+            FrameBytes::Scattered(samples) => (None, Some(samples.iter_mut().map(|bytes| &mut **bytes)))
+        };
+        contiguous.into_iter().flatten().chain(scattered.into_iter().flatten()).map(move |bytes| SampleMut { bytes, encoding, clips })
+    }
+    fn bytes(&self, channel : u32) -> &[u8]
+    {
+        assert!(channel < self.len(), "Channel is out of range.");
+        let size = self.encoding.size();
+        match &self.samples
+        {
+            FrameBytes::Contiguous(bytes) => &bytes[channel as usize * size..(channel as usize + 1) * size],
+            FrameBytes::Scattered(samples) => samples[channel as usize]
+        }
+    }
+    fn bytes_mut(&mut self, channel : u32) -> &mut [u8]
+    {
+        assert!(channel < self.len(), "Channel is out of range.");
+        let size = self.encoding.size();
+        match &mut self.samples
+        {
+            FrameBytes::Contiguous(bytes) => &mut bytes[channel as usize * size..(channel as usize + 1) * size],
+            FrameBytes::Scattered(samples) => samples[channel as usize]
+        }
+    }
+}
+
+/// Mutable handle to a single channel of an AudioBuffer.
+pub struct ChannelMut<'a>
+{
+    bytes : &'a mut [u8],
+    encoding : crate::Encoding,
+    step : usize,
+    offset : usize,
+    clips : &'a std::cell::Cell<u64>
+}
+impl<'a> ChannelMut<'a>
+{
+    pub(crate) fn init(bytes : &'a mut [u8], encoding : crate::Encoding, step : usize, offset : usize, clips : &'a std::cell::Cell<u64>) -> Self
+    {
+        Self { bytes, encoding, step, offset, clips }
+    }
+    /// Get a buffer size in frames.
+    pub fn len(&self) -> u32 { self.bytes.len().checked_div(self.step).unwrap_or(0) as u32 }
+    /// Check whether the channel has no frames.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Read the sample of the frame.
+    pub fn get(&self, frame : u32) -> f64
+    {
+        let start = frame as usize * self.step + self.offset;
+        self.encoding.get(&self.bytes[start..start + self.encoding.size()])
+    }
+    /// Write the sample of the frame, following the clip policy of the buffer.
+    pub fn set(&mut self, frame : u32, data : f64)
+    {
+        let start = frame as usize * self.step + self.offset;
+        if self.encoding.set(data, &mut self.bytes[start..start + self.encoding.size()]) { self.clips.set(self.clips.get() + 1); }
+    }
+    /// Iterate over the samples of every frame.
+    pub fn iter_mut(&mut self) -> ChannelIterMut<'_>
+    {
+        ChannelIterMut { chunks: self.bytes.chunks_exact_mut(self.step.max(1)), encoding: self.encoding, offset: self.offset, clips: self.clips }
+    }
+}
+
+/// Mutable iterator over the samples of a channel.
+pub struct ChannelIterMut<'a>
+{
+    chunks : std::slice::ChunksExactMut<'a, u8>,
+    encoding : crate::Encoding,
+    offset : usize,
+    clips : &'a std::cell::Cell<u64>
+}
+impl<'a> Iterator for ChannelIterMut<'a>
+{
+    type Item = SampleMut<'a>;
+    fn next(&mut self) -> Option<SampleMut<'a>>
+    {
+        let chunk = self.chunks.next()?;
+        Some(SampleMut { bytes: &mut chunk[self.offset..self.offset + self.encoding.size()], encoding: self.encoding, clips: self.clips })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.chunks.size_hint() }
+}
+impl ExactSizeIterator for ChannelIterMut<'_> { }
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{AudioBuffer, Layout, SampleFormat};
+
+    /// Create a silent 16-bit buffer of 2 channels and 3 frames.
+    fn silent(layout : Layout) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init(2, 16, 3).unwrap();
+        buffer.set_layout(layout);
+        buffer
+    }
+    fn number(index : usize) -> f64 { (index + 1) as f64 / 64.0 }
+
+    #[test]
+    fn mutable_iterators_go_in_interleaved_order()
+    {
+        let expected : Vec<f64> = (0..6).map(number).collect();
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            let mut samples = silent(layout);
+            for (index, mut sample) in samples.samples_mut().enumerate() { sample.set(number(index)); }
+            assert_eq!(samples.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+            let mut frames = silent(layout);
+            for (frame, mut samples) in frames.frames_mut().enumerate()
+            {
+                assert_eq!(samples.len(), 2);
+                for channel in 0..2 { samples.set(channel, number(frame * 2 + channel as usize)); }
+            }
+            assert_eq!(frames.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+            let mut frames = silent(layout);
+            for (frame, mut samples) in frames.frames_mut().enumerate()
+            {
+                for (channel, mut sample) in samples.iter_mut().enumerate() { sample.set(number(frame * 2 + channel)); }
+            }
+            assert_eq!(frames.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+            assert!(frames.frames_mut().enumerate().all(|(frame, samples)| samples.get(1) == number(frame * 2 + 1)), "{layout:?}");
+            let mut channels = silent(layout);
+            for channel in 0..2
+            {
+                let mut samples = channels.channel_mut(channel);
+                assert_eq!(samples.len(), 3);
+                for (frame, mut sample) in samples.iter_mut().enumerate() { sample.set(number(frame * 2 + channel as usize)); }
+                assert_eq!(samples.get(2), number(4 + channel as usize), "{layout:?}");
+            }
+            assert_eq!(channels.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+        }
+    }
+    #[test]
+    fn mutable_iterators_count_clips()
+    {
+        let mut buffer = silent(Layout::Planar);
+        for mut sample in buffer.samples_mut() { sample.set(2.0); }
+        assert_eq!(buffer.clip_count(), 6);
+        assert!(buffer.samples().all(|sample| sample == 1.0 - 1.0 / 32768.0));
+        for mut frame in buffer.frames_mut() { frame.set(1, -2.0); }
+        buffer.channel_mut(0).set(2, 1.5);
+        assert_eq!(buffer.clip_count(), 10);
+        for mut sample in buffer.channel_mut(1).iter_mut() { sample.set(0.5); }
+        for mut sample in buffer.samples_mut() { sample.set_as(i16::MIN); }
+        assert_eq!(buffer.clip_count(), 10);
+        assert!(buffer.samples_mut().all(|sample| sample.get() == -1.0 && sample.get_as::<i16>() == i16::MIN));
+    }
+    #[test]
+    fn mutable_iterators_leave_clones_alone()
+    {
+        let mut original = silent(Layout::Interleaved);
+        original.write_slice(&(0..6).map(number).collect::<Vec<_>>()).unwrap();
+        let writes : [fn(&mut AudioBuffer); 3] =
+        [
+            |target| for mut sample in target.samples_mut() { sample.set(0.0); },
+            |target| for mut frame in target.frames_mut() { frame.set(0, 0.0); },
+            |target| target.channel_mut(1).set(0, 0.0)
+        ];
+        for write in writes
+        {
+            let mut target = original.clone();
+            write(&mut target);
+            assert!(!original.is_shared() && target.samples().any(|sample| sample == 0.0));
+            assert_eq!(original.samples().collect::<Vec<_>>(), (0..6).map(number).collect::<Vec<_>>());
+        }
+    }
+    #[test]
+    fn buffers_collect_and_extend()
+    {
+        let mono : AudioBuffer = [0.25, -0.5, 0.75].into_iter().collect();
+        assert_eq!((mono.channels(), mono.size(), mono.format(), mono.bit_depth()), (1, 3, SampleFormat::Float, 32));
+        assert_eq!(mono.samples().collect::<Vec<_>>(), [0.25, -0.5, 0.75]);
+        let mut stereo : AudioBuffer = [[0.25, -0.25], [0.5, -0.5]].into_iter().collect();
+        assert_eq!((stereo.channels(), stereo.size()), (2, 2));
+        stereo.extend([0.125, -0.125]);
+        stereo.extend([[1.0, -1.0]]);
+        stereo.extend(std::iter::empty::<f64>());
+        assert_eq!(stereo.size(), 4);
+        assert_eq!(stereo.samples().collect::<Vec<_>>(), [0.25, -0.25, 0.5, -0.5, 0.125, -0.125, 1.0, -1.0]);
+        let mut empty : AudioBuffer = std::iter::empty::<f64>().collect();
+        assert_eq!((empty.channels(), empty.size(), empty.samples_mut().count()), (1, 0, 0));
+    }
+    #[test]
+    #[should_panic(expected = "Samples do not fill whole frames.")]
+    fn partial_frames_are_rejected()
+    {
+        let mut stereo : AudioBuffer = [[0.25, -0.25]].into_iter().collect();
+        stereo.extend([0.5]);
+    }
+    #[test]
+    fn buffers_without_samples_iterate_nothing()
+    {
+        let mut empty = AudioBuffer::default();
+        assert_eq!((empty.samples_mut().count(), empty.frames_mut().count()), (0, 0));
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            // Channels and frames without a sample format, as a device announces them before its first samples.
+            let mut unformatted = AudioBuffer { channels: 2, buffer_size: 4, layout, ..AudioBuffer::default() };
+            assert_eq!((unformatted.samples_mut().count(), unformatted.frames_mut().count(), unformatted.channel_mut(1).iter_mut().count()), (0, 0, 0));
+        }
+    }
+}
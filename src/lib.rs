@@ -1,160 +1,1146 @@
+mod batch;
+mod convert;
+mod dither;
+mod envelope;
+mod error;
+mod g711;
+mod iter;
+mod loudness;
+mod matrix;
+mod meter;
+mod normalize;
+mod sample;
+mod speakers;
+mod view;
+mod wave;
+pub use convert::Conversion;
+pub use dither::{Dither, NoiseShaping};
+pub use envelope::{FadeCurve, GainEnvelope};
+pub use error::{Error, Result};
+pub use iter::{Channel, ChannelIter, ChannelIterMut, ChannelMut, Frame, FrameIter, FrameMut, Frames, FramesMut, SampleMut, Samples, SamplesMut};
+pub use loudness::LoudnessMeter;
+pub use matrix::{MixMatrix, PanLaw};
+pub use meter::{Level, Meter};
+pub use normalize::{Normalization, Normalized};
+pub use sample::{Sample, I24};
+pub use speakers::{ChannelLayout, ChannelPosition};
+pub use view::{AudioRead, AudioWrite, AudioView, AudioViewMut};
+pub use wave::{WaveReader, WaveWriter};
+
+/// Encoding of the samples stored in an AudioBuffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormat
+{
+    /// Signed two's complement integer PCM, up to 32 bits.
+    #[default]
+    Int,
+    /// Unsigned offset-binary integer PCM, up to 32 bits, where silence is half of the range.
+    UInt,
+    /// IEEE 754 floating point, 32 or 64 bits.
+    Float,
+    /// ITU-T G.711 A-law companded, 8 bits.
+    ALaw,
+    /// ITU-T G.711 mu-law companded, 8 bits.
+    MuLaw
+}
+impl SampleFormat
+{
+    /// Check whether the valid bit depth can be stored in the format.
+    pub fn supports(self, bit_depth : u32) -> bool
+    {
+        match self
+        {
+            Self::Int | Self::UInt => (1..=32).contains(&bit_depth),
+            Self::Float => matches!(bit_depth, 32 | 64),
+            Self::ALaw | Self::MuLaw => bit_depth == 8
+        }
+    }
+    /// Check whether the format stores negative values directly instead of with an offset.
+    pub fn is_signed(self) -> bool { self != Self::UInt }
+    fn code(self) -> u8
+    {
+        match self
+        {
+            Self::Int => 1,
+            Self::Float => 2,
+            Self::UInt => 3,
+            Self::ALaw => 4,
+            Self::MuLaw => 5
+        }
+    }
+    fn from_code(code : u8) -> Option<Self>
+    {
+        match code
+        {
+            1 => Some(Self::Int),
+            2 => Some(Self::Float),
+            3 => Some(Self::UInt),
+            4 => Some(Self::ALaw),
+            5 => Some(Self::MuLaw),
+            _ => None
+        }
+    }
+}
+
+/// Arrangement of the channels in an AudioBuffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout
+{
+    /// Samples of a frame are stored next to each other.
+    #[default]
+    Interleaved,
+    /// Samples of a channel are stored next to each other.
+    Planar
+}
+
+/// Byte order of the samples in an AudioBuffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian
+{
+    /// Least significant byte first.
+    #[default]
+    Little,
+    /// Most significant byte first.
+    Big
+}
+impl Endian
+{
+    /// Get the byte order of the target.
+    pub fn native() -> Self { if cfg!(target_endian = "big") { Self::Big } else { Self::Little } }
+}
+
+/// Position of the valid bits inside a wider sample container.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Justify
+{
+    /// Valid bits are aligned to the most significant bit, padding is in the low bits.
+    #[default]
+    Left,
+    /// Valid bits are aligned to the least significant bit, padding is in the high bits.
+    Right
+}
+
+/// Handling of samples beyond the range of an integer or G.711 AudioBuffer.
+///
+/// Float buffers store any value and never clip.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClipPolicy
+{
+    /// Clamp to the largest value of the sample format.
+    #[default]
+    Saturate,
+    /// Keep the low bits in two's complement, like a plain integer cast.
+    Wrap,
+    /// Leave the stored sample unchanged and report an error from fallible writes.
+    ///
+    /// Writes of many samples, to buffers and views alike, stop at the first rejected sample and keep the samples
+    /// written before it. Slices and frames are written frame by frame in channel order, mixes, gains and fades
+    /// channel by channel.
+    Error
+}
+
+/// Everything needed to turn stored bytes into samples and back.
+#[derive(Clone, Copy)]
+struct Encoding
+{
+    format : SampleFormat,
+    bit_depth : u32,
+    container : u32,
+    justify : Justify,
+    endian : Endian,
+    clip : ClipPolicy
+}
+impl Encoding
+{
+    fn size(self) -> usize { (self.container / 8) as usize }
+    fn stores<S : Sample>(self) -> bool { S::FORMAT == self.format && S::BIT_DEPTH == self.bit_depth && self.container == self.bit_depth }
+    fn get<S : Sample>(self, bytes : &[u8]) -> S
+    {
+        let mut swapped = [0; 8];
+        let bytes = self.little_endian(bytes, &mut swapped);
+        if self.stores::<S>() { return S::decode(bytes); }
+        match (self.format, self.bit_depth)
+        {
+            (SampleFormat::Float, 32) => f32::decode(bytes).convert(),
+            (SampleFormat::Float, _) => f64::decode(bytes).convert(),
+            (SampleFormat::ALaw, _) => g711::alaw_decode(bytes[0]).convert(),
+            (SampleFormat::MuLaw, _) => g711::mulaw_decode(bytes[0]).convert(),
+            _ => S::from_f64(sample::int_to_f64(self.get_int(bytes), self.bit_depth))
+        }
+    }
+    /// Store the sample and report whether it was out of range.
+    fn set<S : Sample>(self, data : S, bytes : &mut [u8]) -> bool
+    {
+        let clipped = if self.stores::<S>()
+        {
+            data.encode(bytes);
+            false
+        }
+        else
+        {
+            match (self.format, self.bit_depth)
+            {
+                (SampleFormat::Float, 32) =>
+                {
+                    data.convert::<f32>().encode(bytes);
+                    false
+                }
+                (SampleFormat::Float, _) =>
+                {
+                    data.to_f64().encode(bytes);
+                    false
+                }
+                (format, bit_depth) =>
+                {
+                    let companded = matches!(format, SampleFormat::ALaw | SampleFormat::MuLaw);
+                    let (value, clipped) = sample::int_clip(data.to_f64(), if companded { 16 } else { bit_depth }, self.clip);
+                    if clipped && self.clip == ClipPolicy::Error { return true; }
+                    match format
+                    {
+                        SampleFormat::ALaw => bytes[0] = g711::alaw_encode(value as i16),
+                        SampleFormat::MuLaw => bytes[0] = g711::mulaw_encode(value as i16),
+                        _ => self.set_int(value, bytes)
+                    }
+                    clipped
+                }
+            }
+        };
+        if self.endian == Endian::Big { bytes.reverse(); }
+        clipped
+    }
+    fn get_int(self, bytes : &[u8]) -> i64
+    {
+        let mut raw = 0_i64;
+        for (bit, byte) in bytes.iter().enumerate() { raw |= (*byte as i64) << (bit * 8); }
+        let shift = match self.justify
+        {
+            Justify::Left => 64 - self.container,
+            Justify::Right => 64 - self.bit_depth
+        };
+        match self.format
+        {
+            SampleFormat::UInt => ((raw << shift) as u64 >> (64 - self.bit_depth)) as i64 - (1 << (self.bit_depth - 1)),
+            _ => (raw << shift) >> (64 - self.bit_depth)
+        }
+    }
+    fn set_int(self, value : i64, bytes : &mut [u8])
+    {
+        let value = if self.format == SampleFormat::UInt { value + (1 << (self.bit_depth - 1)) } else { value };
+        let raw = match self.justify
+        {
+            Justify::Left => value << (self.container - self.bit_depth),
+            Justify::Right => value
+        };
+        let size = bytes.len();
+        bytes.copy_from_slice(&raw.to_le_bytes()[..size]);
+    }
+    fn little_endian<'a>(self, bytes : &'a [u8], swapped : &'a mut [u8; 8]) -> &'a [u8]
+    {
+        if self.endian == Endian::Little { return bytes; }
+        let swapped = &mut swapped[..bytes.len()];
+        swapped.copy_from_slice(bytes);
+        swapped.reverse();
+        swapped
+    }
+}
+
+/// Sample memory of an AudioBuffer, aligned for every native sample type.
+#[derive(Clone, Default)]
+struct Storage
+{
+    words : Vec<u64>,
+    len : usize
+}
+impl Storage
+{
+    fn zeroed(len : usize) -> Self { Self { words: vec![0; len.div_ceil(8)], len } }
+    fn capacity(&self) -> usize { self.words.len() * 8 }
+    fn reserve(&mut self, len : usize)
+    {
+        if len <= self.capacity() { return; }
+        self.words.reserve(len.div_ceil(8) - self.words.len());
+        self.words.resize(self.words.capacity(), 0);
+    }
+    fn set_len(&mut self, len : usize)
+    {
+        assert!(len <= self.capacity(), "Storage is too small.");
+        let old = self.len;
+        self.len = len;
+        if len > old { self.bytes_mut()[old..].fill(0); }
+    }
+    fn bytes(&self) -> &[u8] { unsafe { std::slice::from_raw_parts(self.words.as_ptr() as * const u8, self.len) } }
+    fn bytes_mut(&mut self) -> &mut [u8] { unsafe { std::slice::from_raw_parts_mut(self.words.as_mut_ptr() as * mut u8, self.len) } }
+}
+
+/// Frames converted per channel at a time when transposing planar buffers, small enough to stay in cache.
+const BATCH_FRAMES : usize = 256;
+/// Interleaved samples mixed through a matrix at a time, split between the frames of a block.
+const MIX_SAMPLES : usize = BATCH_FRAMES * 8;
+/// Sample rate assumed for buffers without one.
+const DEFAULT_RATE : u32 = 48000;
+/// Start of the host clock shared by every device, set when it is first read.
+static EPOCH : std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+
+/// Get the host clock time of the buffer timestamps.
+fn host_time() -> std::time::Duration { EPOCH.get_or_init(std::time::Instant::now).elapsed() }
+
 /// Audio buffer container to read or write byte data into audio sample.
+///
+/// Clones share the same samples until one of them is written to, which copies the samples for that clone only.
+/// Use `deep_copy` to get an unshared copy up front.
+#[derive(Clone, Default)]
 pub struct AudioBuffer
 {
     channels : u32,
     bit_depth : u32,
+    container : u32,
+    justify : Justify,
+    format : SampleFormat,
+    endian : Endian,
+    layout : Layout,
     buffer_size : u32,
-    data : * mut u8,
-    ref_count : * mut u32
+    clip : ClipPolicy,
+    clips : u64,
+    dither : dither::Ditherer,
+    sample_rate : Option<u32>,
+    position : Option<u64>,
+    timestamp : Option<std::time::Duration>,
+    speakers : ChannelLayout,
+    data : std::sync::Arc<Storage>
 }
 impl AudioBuffer
 {
-    /// Create an AudioBuffer.
-    pub fn init(channels : u32, bit_depth : u32, buffer_size : u32) -> Self
+    /// Create an integer AudioBuffer.
+    pub fn init(channels : u32, bit_depth : u32, buffer_size : u32) -> Result<Self> { Self::init_with_format(channels, bit_depth, buffer_size, SampleFormat::Int) }
+    /// Create an AudioBuffer with the given sample format.
+    ///
+    /// Integer bit depths which are not a whole number of bytes are stored left-justified in the smallest container.
+    pub fn init_with_format(channels : u32, bit_depth : u32, buffer_size : u32, format : SampleFormat) -> Result<Self>
     {
-        let size = bit_depth / 8 * buffer_size;
-        unsafe
+        if !format.supports(bit_depth) { return Err(Error::InvalidBitDepth { format, bit_depth }); }
+        let container = bit_depth.div_ceil(8) * 8;
+        let size = Self::storage_size(channels, buffer_size, container)?;
+        Ok(Self
         {
-            let ref_count = std::alloc::alloc(std::alloc::Layout::new::<u32>()) as * mut u32;
-            *ref_count = 1;
-
-            Self
+            channels,
+            bit_depth,
+            container,
+            justify: Justify::Left,
+            format,
+            endian: Endian::Little,
+            layout: Layout::Interleaved,
+            buffer_size,
+            clip: ClipPolicy::Saturate,
+            clips: 0,
+            dither: dither::Ditherer::default(),
+            sample_rate: None,
+            position: None,
+            timestamp: None,
+            speakers: ChannelLayout::from_channels(channels),
+            data: std::sync::Arc::new(Storage::zeroed(size as usize))
+        })
+    }
+    /// Read a single sample from the interleaved index, wrapping around the end of the buffer.
+    ///
+    /// Panics on an empty buffer.
+    pub fn read(&self, index: u32) -> f64
+    {
+        let (channel, frame) = self.wrap(index);
+        self.read_sample(channel, frame)
+    }
+    /// Read a single sample of the channel from the frame.
+    pub fn read_sample(&self, channel : u32, frame : u32) -> f64
+    {
+        self.read_as(channel, frame)
+    }
+    /// Read a single frame into a given slice with one sample per channel.
+    pub fn read_frame(&self, frame : u32, buffer : &mut [f64]) -> Result<()> { AudioRead::read_frame(self, frame, buffer) }
+    /// Read a whole buffer into a given slice of interleaved frames.
+    pub fn read_slice(&self, buffer : &mut [f64]) -> Result<()> { AudioRead::read_slice(self, buffer) }
+    /// Read a whole channel into a given slice.
+    pub fn read_channel(&self, channel : u32, buffer : &mut [f64]) -> Result<()> { AudioRead::read_channel(self, channel, buffer) }
+    /// Write a single sample from the interleaved index, replacing the stored sample.
+    ///
+    /// Out of range samples follow the clip policy and are counted, see `clip_count`. The index wraps around like in
+    /// `read`.
+    pub fn write(&mut self, index: u32, data : f64)
+    {
+        let (channel, frame) = self.wrap(index);
+        self.write_sample(channel, frame, data);
+    }
+    /// Write a single sample of the channel into the frame.
+    pub fn write_sample(&mut self, channel : u32, frame : u32, data : f64)
+    {
+        self.write_as(channel, frame, data);
+    }
+    /// Write a single frame from a given slice with one sample per channel.
+    pub fn write_frame(&mut self, frame : u32, buffer : &[f64]) -> Result<()> { AudioWrite::write_frame(self, frame, buffer) }
+    /// Write a whole buffer from a given slice of interleaved frames.
+    pub fn write_slice(&mut self, buffer : &[f64]) -> Result<()> { AudioWrite::write_slice(self, buffer) }
+    /// Write a whole channel from a given slice.
+    pub fn write_channel(&mut self, channel : u32, buffer : &[f64]) -> Result<()> { AudioWrite::write_channel(self, channel, buffer) }
+    /// Add a value onto a single sample from the interleaved index, wrapping around like in `read`.
+    pub fn mix(&mut self, index : u32, data : f64)
+    {
+        let (channel, frame) = self.wrap(index);
+        self.mix_sample(channel, frame, data);
+    }
+    /// Add a value onto a single sample of the channel in the frame.
+    pub fn mix_sample(&mut self, channel : u32, frame : u32, data : f64) { AudioWrite::mix_sample(self, channel, frame, data) }
+    /// Add a given slice of interleaved frames onto the whole buffer.
+    pub fn mix_slice(&mut self, buffer : &[f64]) -> Result<()> { AudioWrite::mix_slice(self, buffer) }
+    /// Get a window over a range of frames and channels without copying.
+    pub fn view(&self, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> Result<AudioView<'_>> { AudioView::init(self, frames, channels) }
+    /// Get a mutable window over a range of frames and channels without copying.
+    pub fn view_mut(&mut self, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> Result<AudioViewMut<'_>> { AudioViewMut::init(self, frames, channels) }
+    /// Iterate over every sample in interleaved order.
+    pub fn samples(&self) -> Samples<'_, Self> { AudioRead::samples(self) }
+    /// Iterate over every frame.
+    pub fn frames(&self) -> Frames<'_, Self> { AudioRead::frames(self) }
+    /// Get a single channel, panicking if it does not exist.
+    pub fn channel(&self, channel : u32) -> Channel<'_, Self> { AudioRead::channel(self, channel) }
+    /// Iterate mutably over every sample in interleaved order.
+    pub fn samples_mut(&mut self) -> SamplesMut<'_>
+    {
+        let (encoding, layout, channels, frames) = (self.encoding(), self.layout, self.channels as usize, self.buffer_size as usize);
+        let (bytes, clips) = self.bytes_and_clips();
+        SamplesMut::init(bytes, encoding, layout, channels, frames, clips)
+    }
+    /// Iterate mutably over every frame.
+    pub fn frames_mut(&mut self) -> FramesMut<'_>
+    {
+        let (encoding, layout, channels, frames) = (self.encoding(), self.layout, self.channels as usize, self.buffer_size as usize);
+        let (bytes, clips) = self.bytes_and_clips();
+        FramesMut::init(bytes, encoding, layout, channels, frames, clips)
+    }
+    /// Get a single channel mutably, panicking if it does not exist.
+    pub fn channel_mut(&mut self, channel : u32) -> ChannelMut<'_>
+    {
+        assert!(channel < self.channels, "Channel is out of range.");
+        let (encoding, size) = (self.encoding(), self.sample_size());
+        let (range, step, offset) = match self.layout
+        {
+            Layout::Interleaved => (0..self.data.len, size * self.channels as usize, channel as usize * size),
+            Layout::Planar => (self.channel_range(channel).unwrap_or_default(), size, 0)
+        };
+        let (bytes, clips) = self.bytes_and_clips();
+        ChannelMut::init(&mut bytes[range], encoding, step, offset, clips)
+    }
+    /// Get the bytes of a channel without copying, only available in planar layout.
+    pub fn channel_bytes(&self, channel : u32) -> Option<&[u8]>
+    {
+        let range = self.channel_range(channel)?;
+        Some(&self.bytes()[range])
+    }
+    /// Get the mutable bytes of a channel without copying, only available in planar layout.
+    pub fn channel_bytes_mut(&mut self, channel : u32) -> Option<&mut [u8]>
+    {
+        let range = self.channel_range(channel)?;
+        Some(&mut self.bytes_mut()[range])
+    }
+    /// Rearrange the stored samples into the given layout.
+    pub fn set_layout(&mut self, layout : Layout)
+    {
+        if self.layout == layout { return; }
+        let (size, channels, frames) = (self.sample_size(), self.channels as usize, self.buffer_size as usize);
+        let source = self.bytes().to_vec();
+        let data = self.bytes_mut();
+        for frame in 0..frames
+        {
+            for channel in 0..channels
             {
-                channels,
-                bit_depth,
-                buffer_size,
-                data: std::alloc::alloc_zeroed(std::alloc::Layout::array::<u8>(size as usize).expect("Cannot allocate memory.")),
-                ref_count 
+                let (interleaved, planar) = ((frame * channels + channel) * size, (channel * frames + frame) * size);
+                let (from, to) = if layout == Layout::Planar { (interleaved, planar) } else { (planar, interleaved) };
+                data[to..to + size].copy_from_slice(&source[from..from + size]);
             }
         }
+        self.layout = layout;
     }
-    /// Read a single sample from the index.
-    pub fn read(&self, index: u32) -> f64
+    /// Read a single sample of the channel from the frame as a native sample.
+    pub fn read_as<S : Sample>(&self, channel : u32, frame : u32) -> S
+    {
+        let (offset, encoding) = (self.offset(channel, frame), self.encoding());
+        encoding.get(&self.bytes()[offset..offset + encoding.size()])
+    }
+    /// Write a single sample of the channel into the frame from a native sample.
+    pub fn write_as<S : Sample>(&mut self, channel : u32, frame : u32, data : S) { self.store(channel, frame, data); }
+    /// Read a whole buffer into a given slice of interleaved native samples.
+    pub fn read_slice_as<S : Sample>(&self, buffer : &mut [S]) -> Result<()>
+    {
+        check_len((self.buffer_size * self.channels) as usize, buffer.len())?;
+        for (block, samples) in buffer.chunks_mut(BATCH_FRAMES * self.channels.max(1) as usize).enumerate() { self.decode_frames(block * BATCH_FRAMES, samples); }
+        Ok(())
+    }
+    /// Write a whole buffer from a given slice of interleaved native samples.
+    pub fn write_slice_as<S : Sample>(&mut self, buffer : &[S]) -> Result<()>
+    {
+        check_len((self.buffer_size * self.channels) as usize, buffer.len())?;
+        let channels = self.channels.max(1) as usize;
+        for (block, samples) in buffer.chunks(BATCH_FRAMES * channels).enumerate() { self.encode_frames(block * BATCH_FRAMES, samples)?; }
+        Ok(())
+    }
+    /// Get the native samples of a channel without copying.
+    ///
+    /// Only available in planar layout when the buffer stores exactly `S` in native byte order.
+    pub fn channel_as<S : Sample>(&self, channel : u32) -> Option<&[S]>
+    {
+        if !self.encoding().stores::<S>() || std::mem::size_of::<S>() != self.sample_size() || self.endian != Endian::native() { return None; }
+        let bytes = self.channel_bytes(channel)?;
+        let (head, samples, _) = unsafe { bytes.align_to::<S>() };
+        if head.is_empty() { Some(samples) } else { None }
+    }
+    /// Get the mutable native samples of a channel without copying.
+    ///
+    /// Only available in planar layout when the buffer stores exactly `S` in native byte order.
+    pub fn channel_as_mut<S : Sample>(&mut self, channel : u32) -> Option<&mut [S]>
+    {
+        if !self.encoding().stores::<S>() || std::mem::size_of::<S>() != self.sample_size() || self.endian != Endian::native() { return None; }
+        let bytes = self.channel_bytes_mut(channel)?;
+        let (head, samples, _) = unsafe { bytes.align_to_mut::<S>() };
+        if head.is_empty() { Some(samples) } else { None }
+    }
+    /// Dither and store a single sample, counting it if it was out of range.
+    fn store<S : Sample>(&mut self, channel : u32, frame : u32, data : S) -> bool
     {
-        let bit_depth = self.bit_depth / 8;
-        let real_index = index % self.buffer_size * bit_depth;
-        let limit = if self.bit_depth == 8 { i8::MAX as i32 }
-        else if self.bit_depth == 16 { i16::MAX as i32 }
-        else if self.bit_depth == 24 { 2_i32.pow(24) - 1 }
-        else if self.bit_depth == 32 { i32::MAX }
-        else { 0 };
-        let mut data = 0;
-        for bit in 0..bit_depth
+        let (offset, encoding) = (self.offset(channel, frame), self.encoding());
+        let clipped = if self.dithers::<S>()
         {
-            data = data << 8 | unsafe { *self.data.offset((real_index + bit_depth - bit) as isize) as u32 }
+            let value = self.dither.process(channel, data.to_f64(), self.bit_depth);
+            encoding.set(value, &mut self.bytes_mut()[offset..offset + encoding.size()])
         }
-        if data > limit as u32 { 0.0 - ((data - limit as u32) as i32 / limit) as f64 } else { (data / limit as u32) as f64 }
+        else { encoding.set(data, &mut self.bytes_mut()[offset..offset + encoding.size()]) };
+        if clipped { self.clips += 1; }
+        clipped
     }
-    /// Read a whole buffer into a given slice.
-    pub fn read_slice(&self, buffer : &mut [f64])
+    /// Check whether writing `S` loses precision which the dither should cover.
+    fn dithers<S : Sample>(&self) -> bool
     {
-        if buffer.len() != self.buffer_size as usize { return; }
-        for index in 0..self.buffer_size { buffer[index as usize] = self.read(index); }
+        self.dither.is_active() && matches!(self.format, SampleFormat::Int | SampleFormat::UInt) && (S::FORMAT == SampleFormat::Float || S::BIT_DEPTH > self.bit_depth)
     }
-    /// Write a single sample from the index.
-    pub fn write(&mut self, index: u32, data : f64)
+    /// Write samples which are already on the grid of the buffer without dithering them again.
+    fn write_exact(&mut self, samples : &[f64]) -> Result<()>
     {
-        let bit_depth = self.bit_depth / 8;
-        let real_index = index % self.buffer_size * bit_depth;
-        let limit = if self.bit_depth == 8 { i8::MAX as i32 }
-        else if self.bit_depth == 16 { i16::MAX as i32 }
-        else if self.bit_depth == 24 { 2_i32.pow(24) - 1 }
-        else if self.bit_depth == 32 { i32::MAX }
-        else { 0 };
-        let restored = if data < 0.0 { (data * limit as f64) as i32 } else { (data * limit as f64) as i32 };
-        for bit in 0..bit_depth
-        {
-            unsafe
+        let dither = std::mem::take(&mut self.dither);
+        let result = self.write_slice_as(samples);
+        self.dither = dither;
+        result
+    }
+    /// Decode interleaved frames from the given frame on, converting planar samples block by block to stay in cache.
+    fn decode_frames<S : Sample>(&self, start : usize, samples : &mut [S])
+    {
+        let (encoding, channels, size) = (self.encoding(), self.channels as usize, self.sample_size());
+        let bytes = self.bytes();
+        match self.layout
+        {
+            Layout::Interleaved =>
             {
-                *self.data.offset((real_index + bit) as isize) += ((restored & (0xFF << bit * 8)) >> (bit * 8)) as u8
+                let offset = start * channels * size;
+                batch::decode(encoding, &bytes[offset..offset + samples.len() * size], samples.iter_mut());
+            }
+            Layout::Planar =>
+            {
+                let (frames, len) = (self.buffer_size as usize, samples.len() / channels.max(1));
+                for channel in 0..channels
+                {
+                    let offset = (channel * frames + start) * size;
+                    batch::decode(encoding, &bytes[offset..offset + len * size], samples.iter_mut().skip(channel).step_by(channels));
+                }
             }
         }
     }
-    /// Write a whole buffer from a given slice.
-    pub fn write_slice(&mut self, buffer : &[f64])
+    /// Encode interleaved frames from the given frame on, counting out of range samples.
+    fn encode_frames<S : Sample>(&mut self, start : usize, samples : &[S]) -> Result<()>
     {
-        if buffer.len() != self.buffer_size as usize { return; }
-        for index in 0..self.buffer_size { self.write(index, buffer[index as usize]); }
+        let (encoding, channels, size) = (self.encoding(), self.channels as usize, self.sample_size());
+        // Planar batches go channel by channel, so rejecting samples takes the per-sample path to stop in frame order.
+        if self.dithers::<S>() || (self.clip == ClipPolicy::Error && self.layout == Layout::Planar)
+        {
+            for (index, sample) in samples.iter().enumerate()
+            {
+                let (channel, frame) = ((index % channels) as u32, (start + index / channels) as u32);
+                if self.store(channel, frame, *sample) && self.clip == ClipPolicy::Error { return Err(Error::Clipped { channel, frame }); }
+            }
+            return Ok(());
+        }
+        let frames = self.buffer_size as usize;
+        let bytes = std::sync::Arc::make_mut(&mut self.data).bytes_mut();
+        let result = match self.layout
+        {
+            Layout::Interleaved =>
+            {
+                let offset = start * channels * size;
+                let result = batch::encode(encoding, samples.iter(), &mut bytes[offset..offset + samples.len() * size]);
+                result.map_err(|index| (index % channels, start + index / channels))
+            }
+            Layout::Planar =>
+            {
+                let len = samples.len() / channels.max(1);
+                (0..channels).try_fold(0, |clips, channel|
+                {
+                    let offset = (channel * frames + start) * size;
+                    let result = batch::encode(encoding, samples.iter().skip(channel).step_by(channels), &mut bytes[offset..offset + len * size]);
+                    Ok(clips + result.map_err(|index| (channel, start + index))?)
+                })
+            }
+        };
+        match result
+        {
+            Ok(clips) =>
+            {
+                self.clips += clips;
+                Ok(())
+            }
+            Err((channel, frame)) =>
+            {
+                self.clips += 1;
+                Err(Error::Clipped { channel: channel as u32, frame: frame as u32 })
+            }
+        }
     }
-    /// Get a buffer size.
+    /// Decode samples of a channel from the given frame on.
+    fn decode_channel(&self, channel : u32, start : usize, samples : &mut [f64])
+    {
+        let (encoding, channels, size) = (self.encoding(), self.channels as usize, self.sample_size());
+        let bytes = self.bytes();
+        match self.layout
+        {
+            Layout::Interleaved =>
+            {
+                let offset = (start * channels + channel as usize) * size;
+                for (sample, chunk) in samples.iter_mut().zip(bytes[offset..].chunks(channels * size)) { *sample = encoding.get(&chunk[..size]); }
+            }
+            Layout::Planar =>
+            {
+                let offset = (channel as usize * self.buffer_size as usize + start) * size;
+                batch::decode(encoding, &bytes[offset..offset + samples.len() * size], samples.iter_mut());
+            }
+        }
+    }
+    /// Encode samples of a channel from the given frame on, counting out of range samples.
+    fn encode_channel(&mut self, channel : u32, start : usize, samples : &[f64]) -> Result<()>
+    {
+        if self.layout == Layout::Interleaved || self.dithers::<f64>()
+        {
+            for (frame, sample) in (start as u32..).zip(samples)
+            {
+                if self.store(channel, frame, *sample) && self.clip == ClipPolicy::Error { return Err(Error::Clipped { channel, frame }); }
+            }
+            return Ok(());
+        }
+        let (encoding, size) = (self.encoding(), self.sample_size());
+        let offset = (channel as usize * self.buffer_size as usize + start) * size;
+        let bytes = std::sync::Arc::make_mut(&mut self.data).bytes_mut();
+        match batch::encode(encoding, samples.iter(), &mut bytes[offset..offset + samples.len() * size])
+        {
+            Ok(clips) =>
+            {
+                self.clips += clips;
+                Ok(())
+            }
+            Err(index) =>
+            {
+                self.clips += 1;
+                Err(Error::Clipped { channel, frame: (start + index) as u32 })
+            }
+        }
+    }
+    fn encoding(&self) -> Encoding
+    {
+        Encoding { format: self.format, bit_depth: self.bit_depth, container: self.container, justify: self.justify, endian: self.endian, clip: self.clip }
+    }
+    fn channel_range(&self, channel : u32) -> Option<std::ops::Range<usize>>
+    {
+        if self.layout != Layout::Planar || channel >= self.channels { return None; }
+        let size = self.buffer_size as usize * self.sample_size();
+        Some(channel as usize * size..(channel as usize + 1) * size)
+    }
+    fn sample_size(&self) -> usize { (self.container / 8) as usize }
+    fn wrap(&self, index : u32) -> (u32, u32)
+    {
+        let samples = self.buffer_size * self.channels;
+        assert!(samples != 0, "Sample is out of range.");
+        let index = index % samples;
+        (index % self.channels, index / self.channels)
+    }
+    fn offset(&self, channel : u32, frame : u32) -> usize
+    {
+        assert!(channel < self.channels && frame < self.buffer_size, "Sample is out of range.");
+        match self.layout
+        {
+            Layout::Interleaved => (frame * self.channels + channel) as usize * self.sample_size(),
+            Layout::Planar => (channel * self.buffer_size + frame) as usize * self.sample_size()
+        }
+    }
+    fn reshape(&mut self, channels : u32, buffer_size : u32) -> Result<()>
+    {
+        let size = Self::storage_size(channels, buffer_size, self.container)?;
+        if channels != self.channels { self.speakers = ChannelLayout::from_channels(channels); }
+        self.channels = channels;
+        self.buffer_size = buffer_size;
+        self.data = std::sync::Arc::new(Storage::zeroed(size as usize));
+        Ok(())
+    }
+    fn storage_size(channels : u32, buffer_size : u32, container : u32) -> Result<u32>
+    {
+        channels.checked_mul(buffer_size).and_then(|samples| samples.checked_mul(container / 8))
+            .ok_or(Error::TooLarge { channels, frames: buffer_size, container })
+    }
+    fn bytes(&self) -> &[u8] { self.data.bytes() }
+    fn bytes_mut(&mut self) -> &mut [u8] { std::sync::Arc::make_mut(&mut self.data).bytes_mut() }
+    fn bytes_and_clips(&mut self) -> (&mut [u8], &std::cell::Cell<u64>)
+    {
+        (std::sync::Arc::make_mut(&mut self.data).bytes_mut(), std::cell::Cell::from_mut(&mut self.clips))
+    }
+    /// Get a buffer size in frames.
     pub fn size(&self) -> u32 { self.buffer_size }
+    /// Get a channel count.
+    pub fn channels(&self) -> u32 { self.channels }
+    /// Get a valid bit depth.
+    pub fn bit_depth(&self) -> u32 { self.bit_depth }
+    /// Get a container width in bits.
+    pub fn container(&self) -> u32 { self.container }
+    /// Get a position of the valid bits in the container.
+    pub fn justify(&self) -> Justify { self.justify }
+    /// Set a container width and justification, converting the stored samples.
+    pub fn set_container(&mut self, container : u32, justify : Justify) -> Result<()>
+    {
+        if !matches!(self.format, SampleFormat::Int | SampleFormat::UInt) || !matches!(container, 8 | 16 | 24 | 32) || self.bit_depth > container
+        {
+            return Err(Error::InvalidContainer { format: self.format, bit_depth: self.bit_depth, container });
+        }
+        if self.container == container && self.justify == justify { return Ok(()); }
+        let size = Self::storage_size(self.channels, self.buffer_size, container)?;
+        let mut samples = vec![0.0; (self.channels * self.buffer_size) as usize];
+        self.read_slice(&mut samples)?;
+        self.container = container;
+        self.justify = justify;
+        self.data = std::sync::Arc::new(Storage::zeroed(size as usize));
+        self.write_exact(&samples)
+    }
+    /// Convert the samples to the given bit depth, dithering them when it is reduced.
+    ///
+    /// The samples are stored left-justified in the smallest container afterwards. With `ClipPolicy::Error` nothing
+    /// changes if any sample is out of range after the dither.
+    pub fn set_bit_depth(&mut self, bit_depth : u32) -> Result<()>
+    {
+        if !self.format.supports(bit_depth) { return Err(Error::InvalidBitDepth { format: self.format, bit_depth }); }
+        let container = bit_depth.div_ceil(8) * 8;
+        if self.bit_depth == bit_depth && self.container == container && self.justify == Justify::Left { return Ok(()); }
+        let size = Self::storage_size(self.channels, self.buffer_size, container)?;
+        let mut samples = vec![0.0; (self.channels * self.buffer_size) as usize];
+        self.read_slice(&mut samples)?;
+        // Convert into a copy so a rejected sample with `ClipPolicy::Error` leaves this buffer as it was.
+        let mut target = self.clone();
+        target.bit_depth = bit_depth;
+        target.container = container;
+        target.justify = Justify::Left;
+        target.data = std::sync::Arc::new(Storage::zeroed(size as usize));
+        let result = if bit_depth < self.bit_depth { target.write_slice(&samples) } else { target.write_exact(&samples) };
+        self.clips = target.clips;
+        if result.is_ok() { *self = target; }
+        result
+    }
+    /// Get a channel layout.
+    ///
+    /// Buffers start out as mono, stereo or discrete channels depending on their channel count.
+    pub fn channel_layout(&self) -> ChannelLayout { self.speakers }
+    /// Set a channel layout with the same channel count as the buffer.
+    pub fn set_channel_layout(&mut self, layout : ChannelLayout) -> Result<()>
+    {
+        if layout.channels() != self.channels { return Err(Error::InvalidChannelLayout { layout, channels: self.channels }); }
+        self.speakers = layout;
+        Ok(())
+    }
+    /// Get a sample rate, if known.
+    pub fn sample_rate(&self) -> Option<u32> { self.sample_rate }
+    /// Set a sample rate.
+    pub fn set_sample_rate(&mut self, sample_rate : Option<u32>) { self.sample_rate = sample_rate; }
+    /// Get a position of the first frame in its stream, if known.
+    pub fn position(&self) -> Option<u64> { self.position }
+    /// Set a position of the first frame in its stream.
+    pub fn set_position(&mut self, position : Option<u64>) { self.position = position; }
+    /// Get a host clock time of the first frame, if known.
+    pub fn timestamp(&self) -> Option<std::time::Duration> { self.timestamp }
+    /// Set a host clock time of the first frame.
+    pub fn set_timestamp(&mut self, timestamp : Option<std::time::Duration>) { self.timestamp = timestamp; }
+    /// Get a duration of the buffer, if the sample rate is known.
+    pub fn duration(&self) -> Option<std::time::Duration> { self.frames_to_duration(self.buffer_size as u64) }
+    /// Convert a frame count into a duration, if the sample rate is known.
+    pub fn frames_to_duration(&self, frames : u64) -> Option<std::time::Duration>
+    {
+        let sample_rate = self.sample_rate.filter(|sample_rate| *sample_rate != 0)? as u128;
+        let nanos = frames as u128 * 1_000_000_000 / sample_rate;
+        Some(std::time::Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32))
+    }
+    /// Convert a duration into the nearest frame count, if the sample rate is known.
+    pub fn duration_to_frames(&self, duration : std::time::Duration) -> Option<u64>
+    {
+        let sample_rate = self.sample_rate.filter(|sample_rate| *sample_rate != 0)? as u128;
+        u64::try_from((duration.as_nanos() * sample_rate + 500_000_000) / 1_000_000_000).ok()
+    }
+    /// Get a host clock time of the frame, if the timestamp and sample rate are known.
+    pub fn timestamp_at(&self, frame : u32) -> Option<std::time::Duration> { self.timestamp?.checked_add(self.frames_to_duration(frame as u64)?) }
+    /// Get the nearest frame of this buffer at a host clock time, which may lie outside the buffer.
+    pub fn frame_at(&self, timestamp : std::time::Duration) -> Option<i64>
+    {
+        let start = self.timestamp?;
+        if timestamp >= start { i64::try_from(self.duration_to_frames(timestamp - start)?).ok() }
+        else { i64::try_from(self.duration_to_frames(start - timestamp)?).ok().map(|frames| -frames) }
+    }
+    /// Get the frame of this buffer where another buffer starts, aligning buffers of different devices by their
+    /// timestamps.
+    ///
+    /// Buffers without timestamps from the same stream are aligned by their positions instead.
+    pub fn offset_of(&self, other : &AudioBuffer) -> Option<i64>
+    {
+        if let (Some(_), Some(timestamp)) = (self.timestamp, other.timestamp) { return self.frame_at(timestamp); }
+        let (start, other) = (i64::try_from(self.position?).ok()?, i64::try_from(other.position?).ok()?);
+        other.checked_sub(start)
+    }
+    /// Copy the sample rate, position and timestamp of another buffer.
+    fn copy_timing(&mut self, other : &AudioBuffer)
+    {
+        self.sample_rate = other.sample_rate;
+        self.position = other.position;
+        self.timestamp = other.timestamp;
+    }
+    /// Convert the samples into a new AudioBuffer.
+    pub fn convert(&self, conversion : &Conversion) -> Result<AudioBuffer>
+    {
+        let mut destination = conversion.destination(self.buffer_size)?;
+        self.convert_into(&mut destination)?;
+        Ok(destination)
+    }
+    /// Convert the samples into the format, layout and channels of a destination, following its dither and clip policy.
+    ///
+    /// Channels are mixed with the standard matrix between the channel layouts of both buffers. The destination is
+    /// resized to the frame count of this buffer and takes over its sample rate, position and timestamp.
+    pub fn convert_into(&self, destination : &mut AudioBuffer) -> Result<()>
+    {
+        self.remix_into(destination, &MixMatrix::between(self.speakers, destination.speakers))
+    }
+    /// Mix the channels into a destination through a matrix, like `convert_into`.
+    pub fn remix_into(&self, destination : &mut AudioBuffer, matrix : &MixMatrix) -> Result<()>
+    {
+        destination.resize(self.buffer_size)?;
+        destination.copy_timing(self);
+        self.remix_frames(destination, matrix)
+    }
+    /// Mix every frame into a destination of the same frame count through a matrix, block by block on the stack.
+    fn remix_frames(&self, destination : &mut AudioBuffer, matrix : &MixMatrix) -> Result<()>
+    {
+        if matrix.inputs() != self.channels || matrix.outputs() != destination.channels
+        {
+            return Err(Error::MatrixMismatch { inputs: matrix.inputs(), outputs: matrix.outputs(), from: self.channels, to: destination.channels });
+        }
+        let (inputs, outputs, frames) = (self.channels as usize, destination.channels as usize, self.buffer_size as usize);
+        let block = MIX_SAMPLES / inputs.max(outputs).max(1);
+        if block == 0
+        {
+            // Too many channels for a single frame on the stack, so mix channel by channel into silence instead.
+            destination.clear();
+            return mix_channels(self, destination, |output, input| matrix.gain(output, input).unwrap_or_default(), |_| 1.0);
+        }
+        let (mut source, mut target) = ([0.0; MIX_SAMPLES], [0.0; MIX_SAMPLES]);
+        for start in (0..frames).step_by(block)
+        {
+            let len = block.min(frames - start);
+            let (source, target) = (&mut source[..len * inputs], &mut target[..len * outputs]);
+            self.decode_frames(start, source);
+            matrix.apply(source, target);
+            destination.encode_frames(start, target)?;
+        }
+        Ok(())
+    }
+    /// Add the samples times a gain onto a destination buffer or view, see `AudioRead::mix_into`.
+    pub fn mix_into<W : AudioWrite + ?Sized>(&self, destination : &mut W, gain : f64) -> Result<()> { AudioRead::mix_into(self, destination, gain) }
+    /// Add the samples through a matrix times a gain onto a destination buffer or view without allocating.
+    pub fn mix_into_with<W : AudioWrite + ?Sized>(&self, destination : &mut W, matrix : &MixMatrix, gain : f64) -> Result<()>
+    {
+        AudioRead::mix_into_with(self, destination, matrix, gain)
+    }
+    /// Add the samples of another buffer or view onto this one, like `mix_into` at unity gain.
+    pub fn add<A : AudioRead + ?Sized>(&mut self, other : &A) -> Result<()> { AudioWrite::add(self, other) }
+    /// Multiply every sample by a gain, see `AudioWrite::scale`.
+    pub fn scale(&mut self, gain : f64) -> Result<()> { AudioWrite::scale(self, gain) }
+    /// Multiply the samples by a gain moving linearly from the start gain to the end gain, see
+    /// `AudioWrite::apply_gain_ramp`.
+    pub fn apply_gain_ramp(&mut self, start : f64, end : f64) -> Result<()> { AudioWrite::apply_gain_ramp(self, start, end) }
+    /// Fade in from silence over the leading frames, see `AudioWrite::fade_in`.
+    pub fn fade_in(&mut self, frames : u32, curve : FadeCurve) -> Result<()> { AudioWrite::fade_in(self, frames, curve) }
+    /// Fade out into silence over the trailing frames, see `AudioWrite::fade_out`.
+    pub fn fade_out(&mut self, frames : u32, curve : FadeCurve) -> Result<()> { AudioWrite::fade_out(self, frames, curve) }
+    /// Fade out of this buffer and into another buffer or view, see `AudioWrite::crossfade`.
+    pub fn crossfade<A : AudioRead + ?Sized>(&mut self, other : &A, curve : FadeCurve) -> Result<()> { AudioWrite::crossfade(self, other, curve) }
+    /// Multiply every frame by the gain of an envelope, see `AudioWrite::apply_envelope`.
+    pub fn apply_envelope(&mut self, envelope : &GainEnvelope) -> Result<()> { AudioWrite::apply_envelope(self, envelope) }
+    /// Get a dither.
+    pub fn dither(&self) -> Dither { self.dither.dither() }
+    /// Get a noise shaping filter.
+    pub fn noise_shaping(&self) -> NoiseShaping { self.dither.shaping() }
+    /// Set a dither and noise shaping filter for the following writes of wider samples into an integer buffer.
+    ///
+    /// The noise of every channel is derived from the seed, so the same writes give the same samples. Writes through
+    /// the mutable iterators are not dithered.
+    pub fn set_dither(&mut self, dither : Dither, shaping : NoiseShaping, seed : u64) { self.dither = dither::Ditherer::init(dither, shaping, seed); }
+    /// Restart the dither noise from its seed and clear the noise shaping history.
+    pub fn reset_dither(&mut self) { self.dither.reset(); }
+    /// Get a clip policy.
+    pub fn clip_policy(&self) -> ClipPolicy { self.clip }
+    /// Set a clip policy for the following writes.
+    pub fn set_clip_policy(&mut self, clip : ClipPolicy) { self.clip = clip; }
+    /// Get the number of out of range samples written since the last reset, including rejected ones.
+    pub fn clip_count(&self) -> u64 { self.clips }
+    /// Reset the clip counter.
+    pub fn reset_clip_count(&mut self) { self.clips = 0; }
+    /// Get a sample format.
+    pub fn format(&self) -> SampleFormat { self.format }
+    /// Get a byte order.
+    pub fn endian(&self) -> Endian { self.endian }
+    /// Set a byte order, converting the stored samples.
+    pub fn set_endian(&mut self, endian : Endian)
+    {
+        let size = self.sample_size();
+        // Buffers without a sample format yet have no samples to swap.
+        if self.endian != endian && size != 0
+        {
+            for sample in self.bytes_mut().chunks_exact_mut(size) { sample.reverse(); }
+        }
+        self.endian = endian;
+    }
+    /// Get a memory layout.
+    pub fn layout(&self) -> Layout { self.layout }
     /// Get a size of a byte container.
-    pub fn real_size(&self) -> u32 { self.bit_depth / 8 * self.buffer_size }
-    pub fn resize(&mut self, buffer_size : u32)
+    pub fn real_size(&self) -> u32 { self.container / 8 * self.channels * self.buffer_size }
+    /// Resize the buffer to the given frame count.
+    ///
+    /// Existing samples are kept, new frames are silent. Shrinking keeps the memory so growing again within the capacity
+    /// does not allocate, unless the samples are shared with another clone.
+    pub fn resize(&mut self, buffer_size : u32) -> Result<()>
     {
-        unsafe
+        let size = Self::storage_size(self.channels, buffer_size, self.container)? as usize;
+        let (old_stride, new_stride) = (self.buffer_size as usize * self.sample_size(), buffer_size as usize * self.sample_size());
+        let (channels, layout) = (self.channels as usize, self.layout);
+        let storage = std::sync::Arc::make_mut(&mut self.data);
+        storage.reserve(size);
+        if layout == Layout::Planar && new_stride > old_stride
         {
-            let mut size = self.real_size();
-            std::alloc::dealloc(self.data, std::alloc::Layout::array::<u8>(size as usize).expect("Cannot allocate memory."));
-            self.buffer_size = buffer_size;
-            size = self.real_size();
-            self.data = std::alloc::alloc_zeroed(std::alloc::Layout::array::<u8>(size as usize).expect("Cannot allocate memory."));
+            storage.set_len(size);
+            let data = storage.bytes_mut();
+            for channel in (1..channels).rev() { data.copy_within(channel * old_stride..(channel + 1) * old_stride, channel * new_stride); }
+            for channel in 0..channels { data[channel * new_stride + old_stride..(channel + 1) * new_stride].fill(0); }
         }
+        else if layout == Layout::Planar
+        {
+            let data = storage.bytes_mut();
+            for channel in 1..channels { data.copy_within(channel * old_stride..channel * old_stride + new_stride, channel * new_stride); }
+            storage.set_len(size);
+        }
+        else { storage.set_len(size); }
+        self.buffer_size = buffer_size;
+        Ok(())
+    }
+    /// Get a capacity in frames which the buffer can hold without allocating.
+    pub fn capacity(&self) -> u32
+    {
+        self.data.capacity().checked_div(self.channels as usize * self.sample_size()).unwrap_or(0) as u32
+    }
+    /// Reserve memory for at least the given number of additional frames.
+    pub fn reserve(&mut self, additional : u32) -> Result<()>
+    {
+        let frames = self.buffer_size.checked_add(additional).ok_or(Error::TooLarge { channels: self.channels, frames: u32::MAX, container: self.container })?;
+        let size = Self::storage_size(self.channels, frames, self.container)? as usize;
+        if size > self.data.capacity() { std::sync::Arc::make_mut(&mut self.data).reserve(size); }
+        Ok(())
+    }
+    /// Release memory which is not needed by the current frames.
+    pub fn shrink_to_fit(&mut self)
+    {
+        let words = self.data.len.div_ceil(8);
+        if self.data.words.len() == words { return; }
+        let storage = std::sync::Arc::make_mut(&mut self.data);
+        storage.words.truncate(words);
+        storage.words.shrink_to_fit();
     }
     /// Clear the buffer.
-    pub fn clear(&mut self) { for index in 0..self.real_size() { unsafe { *self.data.offset(index as isize) = 0; } } }
+    pub fn clear(&mut self)
+    {
+        if self.is_shared() { self.data = std::sync::Arc::new(Storage::zeroed(self.data.len)); }
+        else { self.bytes_mut().fill(0); }
+    }
+    /// Check whether the samples are shared with another clone.
+    pub fn is_shared(&self) -> bool { std::sync::Arc::strong_count(&self.data) > 1 }
+    /// Copy the buffer without sharing the samples.
+    pub fn deep_copy(&self) -> Self
+    {
+        Self { data: std::sync::Arc::new(Storage::clone(&self.data)), ..self.clone() }
+    }
 }
-impl std::clone::Clone for AudioBuffer
+impl AudioRead for AudioBuffer
 {
-    fn clone(&self) -> Self
+    fn channels(&self) -> u32 { self.channels }
+    fn size(&self) -> u32 { self.buffer_size }
+    fn read_sample(&self, channel : u32, frame : u32) -> f64 { self.read_as(channel, frame) }
+    fn read_slice(&self, buffer : &mut [f64]) -> Result<()> { self.read_slice_as(buffer) }
+    fn read_channel_at(&self, channel : u32, frame : u32, buffer : &mut [f64]) -> Result<()>
     {
-        unsafe { *self.ref_count += 1; }
-        Self
-        {
-            channels: self.channels,
-            bit_depth: self.bit_depth,
-            buffer_size: self.buffer_size,
-            data : self.data,
-            ref_count : self.ref_count,
-        }
+        check_channel(channel, self.channels)?;
+        view::check_span(frame, buffer.len(), self.buffer_size)?;
+        self.decode_channel(channel, frame as usize, buffer);
+        Ok(())
     }
+    fn channel_layout(&self) -> ChannelLayout { self.speakers }
+    fn position(&self) -> Option<u64> { self.position }
 }
-impl std::default::Default for AudioBuffer
+impl AudioWrite for AudioBuffer
 {
-    fn default() -> Self
+    fn write_sample(&mut self, channel : u32, frame : u32, data : f64) { self.write_as(channel, frame, data); }
+    fn try_write_sample(&mut self, channel : u32, frame : u32, data : f64) -> Result<()>
     {
-        Self
-        {
-            channels: Default::default(),
-            bit_depth: Default::default(),
-            buffer_size: Default::default(),
-            data: std::ptr::null_mut(),
-            ref_count: std::ptr::null_mut()
-        }
+        if self.store(channel, frame, data) && self.clip == ClipPolicy::Error { Err(Error::Clipped { channel, frame }) } else { Ok(()) }
+    }
+    fn write_slice(&mut self, buffer : &[f64]) -> Result<()> { self.write_slice_as(buffer) }
+    fn write_channel_at(&mut self, channel : u32, frame : u32, buffer : &[f64]) -> Result<()>
+    {
+        check_channel(channel, self.channels)?;
+        view::check_span(frame, buffer.len(), self.buffer_size)?;
+        self.encode_channel(channel, frame as usize, buffer)
     }
 }
-unsafe impl Sync for AudioBuffer { }
-unsafe impl Send for AudioBuffer { }
-impl Drop for AudioBuffer
+impl FromIterator<f64> for AudioBuffer
 {
-    fn drop(&mut self)
+    /// Collect samples into a mono buffer of 32-bit floats.
+    fn from_iter<I : IntoIterator<Item = f64>>(iter : I) -> Self
     {
-        unsafe
+        let mut buffer = Self::init_with_format(1, 32, 0, SampleFormat::Float).expect("32-bit floats are supported.");
+        buffer.extend(iter);
+        buffer
+    }
+}
+impl<const N : usize> FromIterator<[f64; N]> for AudioBuffer
+{
+    /// Collect frames into a buffer of 32-bit floats with one channel per element.
+    fn from_iter<I : IntoIterator<Item = [f64; N]>>(iter : I) -> Self
+    {
+        let mut buffer = Self::init_with_format(N as u32, 32, 0, SampleFormat::Float).expect("32-bit floats are supported.");
+        buffer.extend(iter);
+        buffer
+    }
+}
+impl Extend<f64> for AudioBuffer
+{
+    /// Append interleaved samples, panicking unless they fill whole frames.
+    fn extend<I : IntoIterator<Item = f64>>(&mut self, iter : I)
+    {
+        let samples : Vec<f64> = iter.into_iter().collect();
+        if samples.is_empty() { return; }
+        assert!(self.channels > 0, "Cannot extend a buffer without channels.");
+        let channels = self.channels as usize;
+        assert!(samples.len().is_multiple_of(channels), "Samples do not fill whole frames.");
+        let start = self.buffer_size;
+        let frames = u32::try_from(samples.len() / channels).expect("Too many samples.");
+        self.resize(start.checked_add(frames).expect("Too many samples.")).expect("Buffer is too large.");
+        for (index, sample) in samples.into_iter().enumerate() { self.write_sample((index % channels) as u32, start + (index / channels) as u32, sample); }
+    }
+}
+impl<const N : usize> Extend<[f64; N]> for AudioBuffer
+{
+    /// Append frames, panicking if the frame length differs from the channel count.
+    fn extend<I : IntoIterator<Item = [f64; N]>>(&mut self, iter : I)
+    {
+        assert_eq!(self.channels as usize, N, "Frame length does not match the channel count.");
+        self.extend(iter.into_iter().flatten());
+    }
+}
+
+/// Add the weighted sum of the source channels times the gain of each frame onto every destination channel, block by
+/// block on the stack.
+fn mix_channels<A, W>(source : &A, destination : &mut W, gain : impl Fn(u32, u32) -> f64, envelope : impl Fn(usize) -> f64) -> Result<()>
+where A : AudioRead + ?Sized, W : AudioWrite + ?Sized
+{
+    let frames = source.size().min(destination.size()) as usize;
+    let (mut sum, mut block) = ([0.0; BATCH_FRAMES], [0.0; BATCH_FRAMES]);
+    for output in 0..destination.channels()
+    {
+        if (0..source.channels()).all(|input| gain(output, input) == 0.0) { continue; }
+        for first in (0..frames).step_by(BATCH_FRAMES)
         {
-            if *self.ref_count == 1
+            let len = BATCH_FRAMES.min(frames - first);
+            let (sum, block) = (&mut sum[..len], &mut block[..len]);
+            destination.read_channel_at(output, first as u32, sum)?;
+            for input in 0..source.channels()
             {
-                let size = self.real_size();
-                std::alloc::dealloc(self.data, std::alloc::Layout::array::<u8>(size as usize).expect("Cannot allocate memory."));
-                std::alloc::dealloc(self.ref_count as * mut u8, std::alloc::Layout::new::<u32>());
+                let gain = gain(output, input);
+                if gain == 0.0 { continue; }
+                source.read_channel_at(input, first as u32, block)?;
+                for (frame, (sum, sample)) in (first..).zip(sum.iter_mut().zip(block.iter())) { *sum += gain * envelope(frame) * sample; }
             }
-            else { *self.ref_count -= 1; }
+            destination.write_channel_at(output, first as u32, sum)?;
         }
     }
+    Ok(())
+}
+/// Multiply the frames of a range by the gain of each frame, block by block on the stack.
+fn apply_gains<W : AudioWrite + ?Sized>(target : &mut W, frames : std::ops::Range<usize>, gain : impl Fn(usize) -> f64) -> Result<()>
+{
+    let mut block = [0.0; BATCH_FRAMES];
+    for channel in 0..target.channels()
+    {
+        for first in frames.clone().step_by(BATCH_FRAMES)
+        {
+            let block = &mut block[..BATCH_FRAMES.min(frames.end - first)];
+            target.read_channel_at(channel, first as u32, block)?;
+            for (frame, sample) in (first..).zip(block.iter_mut()) { *sample *= gain(frame); }
+            target.write_channel_at(channel, first as u32, block)?;
+        }
+    }
+    Ok(())
+}
+fn check_len(expected : usize, actual : usize) -> Result<()>
+{
+    if expected == actual { Ok(()) } else { Err(Error::LengthMismatch { expected, actual }) }
+}
+fn check_channel(channel : u32, channels : u32) -> Result<()>
+{
+    if channel < channels { Ok(()) } else { Err(Error::ChannelOutOfRange { channel, channels }) }
+}
+
+/// How received blocks reach the output of an AudioDevice.
+enum Route
+{
+    Off,
+    Standard { from : ChannelLayout, to : ChannelLayout, matrix : MixMatrix },
+    Matrix(MixMatrix)
 }
 
 /// Audio device for various reader and writer type.
+///
+/// Shared meters are locked once per block, so readers should only hold them to copy the levels or read the loudness.
 pub struct AudioDevice<R : std::io::Read, W : std::io::Write>
 {
     name : String,
     sample_rate : u32,
     in_buffer : AudioBuffer,
     out_buffer : AudioBuffer,
+    route : Route,
+    fade : (u32, FadeCurve),
+    envelope : Option<GainEnvelope>,
+    in_meter : Option<std::sync::Arc<std::sync::Mutex<Meter>>>,
+    out_meter : Option<std::sync::Arc<std::sync::Mutex<Meter>>>,
+    in_loudness : Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>>,
+    out_loudness : Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>>,
+    in_position : u64,
+    out_position : u64,
     reader : std::io::BufReader<R>,
     writer : std::io::BufWriter<W>
 }
 impl<R : std::io::Read, W : std::io::Write> AudioDevice<R, W>
 {
     /// Create an AudioDevice.
+    ///
+    /// Received buffers carry their stream position and the host clock time of their first frame, the output buffer
+    /// carries the position of the frames being filled. Every device shares the host clock, so buffers of different
+    /// devices align by their timestamps.
     pub fn init(name : &str, reader : R, writer : W) -> Self
     {
         Self
@@ -162,7 +1148,16 @@ impl<R : std::io::Read, W : std::io::Write> AudioDevice<R, W>
             name: String::from(name),
             sample_rate: u32::default(),
             in_buffer: AudioBuffer::default(),
-            out_buffer: AudioBuffer::default(),
+            out_buffer: AudioBuffer { position: Some(0), ..AudioBuffer::default() },
+            route: Route::Off,
+            fade: (u32::MAX, FadeCurve::Linear),
+            envelope: None,
+            in_meter: None,
+            out_meter: None,
+            in_loudness: None,
+            out_loudness: None,
+            in_position: 0,
+            out_position: 0,
             reader: std::io::BufReader::new(reader),
             writer: std::io::BufWriter::new(writer),
         }
@@ -172,24 +1167,23 @@ impl<R : std::io::Read, W : std::io::Write> AudioDevice<R, W>
     /// Get sample rate of the device.
     pub fn get_sample_rate(&self) -> u32 { self.sample_rate }
     /// Set sample rate of the device.
-    pub fn set_sample_rate(&mut self, sample_rate : u32)
+    pub fn set_sample_rate(&mut self, sample_rate : u32) -> Result<()>
     {
-        if sample_rate % 22050 == 0
-        {
-            self.sample_rate = sample_rate;
-            let state_data = ((sample_rate / 22050) as u8 - 1) << 2 | 0b01;
-            let _ = std::io::Write::write(&mut self.writer, &[state_data]);
-            return;
-        }
-        else if sample_rate % 24000 == 0
-        {
-            self.sample_rate = sample_rate;
-            let state_data = ((sample_rate / 24000) as u8 + 31) << 2 | 0b01;
-            let _ = std::io::Write::write(&mut self.writer, &[state_data]);
-            return;
-        }
-        println!("Invalid sample rate!");
+        let state_data = if sample_rate.is_multiple_of(22050) && (1..=32).contains(&(sample_rate / 22050)) { (sample_rate / 22050) as u8 - 1 }
+        else if sample_rate.is_multiple_of(24000) && (1..=32).contains(&(sample_rate / 24000)) { (sample_rate / 24000) as u8 + 31 }
+        else { return Err(Error::InvalidSampleRate(sample_rate)); };
+        std::io::Write::write_all(&mut self.writer, &[(state_data << 2) | 0b01])?;
+        self.sample_rate = sample_rate;
+        self.in_buffer.sample_rate = Some(sample_rate);
+        self.out_buffer.sample_rate = Some(sample_rate);
+        Ok(())
     }
+    /// Get the number of frames received since the device was created.
+    pub fn get_in_position(&self) -> u64 { self.in_position }
+    /// Get the number of frames sent since the device was created.
+    pub fn get_out_position(&self) -> u64 { self.out_position }
+    /// Get the host clock time of the buffer timestamps, shared by every device.
+    pub fn get_time(&self) -> std::time::Duration { host_time() }
     /// Get buffer size of the input.
     pub fn get_in_buffer_size(&self) -> u32 { self.in_buffer.buffer_size }
     /// Get buffer size of the output.
@@ -198,60 +1192,800 @@ impl<R : std::io::Read, W : std::io::Write> AudioDevice<R, W>
     pub fn get_in_channels(&self) -> u32 { self.in_buffer.channels }
     /// Get channel count of the output.
     pub fn get_out_channels(&self) -> u32 { self.out_buffer.channels }
+    /// Get channel layout of the input.
+    pub fn get_in_channel_layout(&self) -> ChannelLayout { self.in_buffer.speakers }
+    /// Get channel layout of the output.
+    pub fn get_out_channel_layout(&self) -> ChannelLayout { self.out_buffer.speakers }
+    /// Set channel layout of the input, which must match the channel count sent by the device.
+    pub fn set_in_channel_layout(&mut self, layout : ChannelLayout) -> Result<()> { self.in_buffer.set_channel_layout(layout) }
+    /// Set channel layout of the output, reshaping the output to its channel count.
+    pub fn set_out_channel_layout(&mut self, layout : ChannelLayout) -> Result<()>
+    {
+        let channels = layout.channels();
+        if !(1..=64).contains(&channels) { return Err(Error::InvalidChannelCount(channels)); }
+        std::io::Write::write_all(&mut self.writer, &[((channels - 1) as u8) << 2 | 0b11])?;
+        self.out_buffer.reshape(channels, self.out_buffer.buffer_size)?;
+        self.out_buffer.speakers = layout;
+        Ok(())
+    }
     /// Get bit depth of the input.
     pub fn get_in_bit_depth(&self) -> u32 { self.in_buffer.bit_depth }
     /// Get bit depth of the output.
     pub fn get_out_bit_depth(&self) -> u32 { self.out_buffer.bit_depth }
-    fn read(&mut self)
+    /// Get sample format of the input.
+    pub fn get_in_format(&self) -> SampleFormat { self.in_buffer.format }
+    /// Get sample format of the output.
+    pub fn get_out_format(&self) -> SampleFormat { self.out_buffer.format }
+    /// Get byte order of the input.
+    pub fn get_in_endian(&self) -> Endian { self.in_buffer.endian }
+    /// Get byte order of the output.
+    pub fn get_out_endian(&self) -> Endian { self.out_buffer.endian }
+    /// Set sample format and bit depth of the output.
+    pub fn set_out_format(&mut self, format : SampleFormat, bit_depth : u32) -> Result<()>
     {
-        std::io::BufRead::fill_buf(&mut self.reader).expect("Failed to fill the buffer.");
-        let buffer = self.reader.buffer();
-        let len = buffer.len();
-        if len == 0 
+        let mut buffer = AudioBuffer::init_with_format(self.out_buffer.channels, bit_depth, self.out_buffer.buffer_size, format)?;
+        buffer.endian = self.out_buffer.endian;
+        buffer.clip = self.out_buffer.clip;
+        buffer.dither = std::mem::take(&mut self.out_buffer.dither);
+        buffer.speakers = self.out_buffer.speakers;
+        buffer.copy_timing(&self.out_buffer);
+        self.out_buffer = buffer;
+        self.send_format()
+    }
+    /// Set byte order of the output.
+    pub fn set_out_endian(&mut self, endian : Endian) -> Result<()>
+    {
+        self.out_buffer.set_endian(endian);
+        self.send_format()
+    }
+    /// Get clip policy of the output.
+    pub fn get_out_clip_policy(&self) -> ClipPolicy { self.out_buffer.clip }
+    /// Set clip policy of the output.
+    pub fn set_out_clip_policy(&mut self, clip : ClipPolicy) { self.out_buffer.clip = clip; }
+    /// Get the number of out of range samples written to the output since the last reset.
+    pub fn get_out_clip_count(&self) -> u64 { self.out_buffer.clips }
+    /// Reset the clip counter of the output.
+    pub fn reset_out_clip_count(&mut self) { self.out_buffer.clips = 0; }
+    /// Set dither and noise shaping of the output.
+    pub fn set_out_dither(&mut self, dither : Dither, shaping : NoiseShaping, seed : u64) { self.out_buffer.set_dither(dither, shaping, seed); }
+    /// Pass every received block to the output through the standard matrix between the input and output layouts.
+    ///
+    /// The matrix follows later changes of either layout and the output takes over the size of the received blocks.
+    pub fn set_standard_route(&mut self)
+    {
+        let (from, to) = (self.in_buffer.speakers, self.out_buffer.speakers);
+        self.route = Route::Standard { from, to, matrix: MixMatrix::between(from, to) };
+    }
+    /// Pass every received block to the output through a matrix, or stop passing it with None.
+    ///
+    /// The output takes over the size of the received blocks.
+    pub fn set_route(&mut self, matrix : Option<MixMatrix>) { self.route = matrix.map_or(Route::Off, Route::Matrix); }
+    /// Get the matrix which passes received blocks to the output.
+    pub fn get_route(&self) -> Option<&MixMatrix>
+    {
+        match &self.route
+        {
+            Route::Off => None,
+            Route::Standard { matrix, .. } | Route::Matrix(matrix) => Some(matrix)
+        }
+    }
+    /// Get the length and curve of the fades when playing starts and stops.
+    pub fn get_fade(&self) -> (u32, FadeCurve) { self.fade }
+    /// Set the length and curve of the fades when playing starts and stops, 0 frames to disable them.
+    ///
+    /// The fades fit into one block, so the default length fades the whole first and last block.
+    pub fn set_fade(&mut self, frames : u32, curve : FadeCurve) { self.fade = (frames, curve); }
+    /// Get the envelope applied to the output.
+    pub fn get_out_envelope(&self) -> Option<&GainEnvelope> { self.envelope.as_ref() }
+    /// Get the envelope applied to the output mutably, to add breakpoints while playing.
+    pub fn get_out_envelope_mut(&mut self) -> Option<&mut GainEnvelope> { self.envelope.as_mut() }
+    /// Apply an envelope over the output positions to every written block, or stop applying it with None.
+    ///
+    /// Breakpoints are dropped once they no longer affect the gain.
+    pub fn set_out_envelope(&mut self, envelope : Option<GainEnvelope>) { self.envelope = envelope; }
+    /// Measure every received block with a shared meter, or stop measuring with None.
+    pub fn set_in_meter(&mut self, meter : Option<std::sync::Arc<std::sync::Mutex<Meter>>>) { self.in_meter = meter; }
+    /// Measure every written block after its fades and envelope with a shared meter, or stop measuring with None.
+    pub fn set_out_meter(&mut self, meter : Option<std::sync::Arc<std::sync::Mutex<Meter>>>) { self.out_meter = meter; }
+    /// Get the meter of the received blocks.
+    pub fn get_in_meter(&self) -> Option<std::sync::Arc<std::sync::Mutex<Meter>>> { self.in_meter.clone() }
+    /// Get the meter of the written blocks.
+    pub fn get_out_meter(&self) -> Option<std::sync::Arc<std::sync::Mutex<Meter>>> { self.out_meter.clone() }
+    /// Measure the loudness of every received block with a shared meter, or stop measuring with None.
+    pub fn set_in_loudness(&mut self, meter : Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>>) { self.in_loudness = meter; }
+    /// Measure the loudness of every written block after its fades and envelope with a shared meter, or stop
+    /// measuring with None.
+    pub fn set_out_loudness(&mut self, meter : Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>>) { self.out_loudness = meter; }
+    /// Get the loudness meter of the received blocks.
+    pub fn get_in_loudness(&self) -> Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>> { self.in_loudness.clone() }
+    /// Get the loudness meter of the written blocks.
+    pub fn get_out_loudness(&self) -> Option<std::sync::Arc<std::sync::Mutex<LoudnessMeter>>> { self.out_loudness.clone() }
+    fn pass(&mut self) -> Result<()>
+    {
+        if let Route::Standard { from, to, .. } = self.route
         {
-            println!("Buffer is empty!");
-            return;
+            if (from, to) != (self.in_buffer.speakers, self.out_buffer.speakers) { self.set_standard_route(); }
         }
+        // Nothing can be passed before both sides have a sample format.
+        if matches!(self.route, Route::Off) || self.in_buffer.container == 0 || self.out_buffer.container == 0 { return Ok(()); }
+        if self.out_buffer.buffer_size != self.in_buffer.buffer_size { self.out_buffer.resize(self.in_buffer.buffer_size)?; }
+        match &self.route
+        {
+            Route::Off => Ok(()),
+            Route::Standard { matrix, .. } | Route::Matrix(matrix) => self.in_buffer.remix_frames(&mut self.out_buffer, matrix)
+        }
+    }
+    fn send_format(&mut self) -> Result<()>
+    {
+        let endian = if self.out_buffer.endian == Endian::Big { 0b100000 } else { 0 };
+        let state_data = (self.out_buffer.format.code() | endian) << 2;
+        std::io::Write::write_all(&mut self.writer, &[state_data])?;
+        Ok(())
+    }
+    /// Read the next packet, None at the end of the stream and whether it carried samples otherwise.
+    fn read(&mut self) -> Result<Option<bool>>
+    {
+        let len = std::io::BufRead::fill_buf(&mut self.reader)?.len();
+        if len == 0 { return Ok(None); }
+        let result = self.receive(len);
+        std::io::BufRead::consume(&mut self.reader, len);
+        result.map(Some)
+    }
+    fn receive(&mut self, len : usize) -> Result<bool>
+    {
+        let buffer = self.reader.buffer();
         let state_var = buffer[0] & 0b11;
         let state_data = buffer[0] >> 2;
         if state_var == 0b01
         {
-            self.sample_rate = if state_data < 32 { (state_data + 1) as u32 * 22050 } else { (state_data - 31) as u32 * 24000  }
+            self.sample_rate = if state_data < 32 { (state_data + 1) as u32 * 22050 } else { (state_data - 31) as u32 * 24000  };
+            self.in_buffer.sample_rate = Some(self.sample_rate);
+            self.out_buffer.sample_rate = Some(self.sample_rate);
         }
-        else if state_var == 0b10 { self.in_buffer.buffer_size = (state_data + 1) as u32 * 32 }
-        else if state_var == 0b11 { self.in_buffer.channels = (state_data as u32) + 1 }
-        if len == 1 { return; }
-        if self.in_buffer.bit_depth != 8 * (buffer.len() - 1) as u32 / (self.in_buffer.buffer_size * self.in_buffer.channels)
+        else if state_var == 0b10 { self.in_buffer.resize((state_data + 1) as u32 * 32)? }
+        else if state_var == 0b11 { self.in_buffer.reshape((state_data as u32) + 1, self.in_buffer.buffer_size)? }
+        else if state_data != 0
         {
-            println!("Bit depth changed!");
-            self.in_buffer.bit_depth = 8 * (buffer.len() - 1) as u32 / (self.in_buffer.buffer_size * self.in_buffer.channels);
+            self.in_buffer.format = SampleFormat::from_code(state_data & 0b11111).ok_or(Error::InvalidFormatCode(state_data & 0b11111))?;
+            self.in_buffer.endian = if state_data & 0b100000 == 0 { Endian::Little } else { Endian::Big };
         }
-        for index in 1..len { unsafe { *self.in_buffer.data.offset(index as isize) = buffer[index]; } }
+        let samples = self.in_buffer.buffer_size * self.in_buffer.channels;
+        if len == 1 || samples == 0 { return Ok(false); }
+        if !(len - 1).is_multiple_of(samples as usize) { return Err(Error::InvalidPayload { len: len - 1, samples }); }
+        let bit_depth = 8 * ((len - 1) / samples as usize) as u32;
+        if !self.in_buffer.format.supports(bit_depth) { return Err(Error::InvalidBitDepth { format: self.in_buffer.format, bit_depth }); }
+        if self.in_buffer.container != bit_depth
+        {
+            let mut in_buffer = AudioBuffer::init_with_format(self.in_buffer.channels, bit_depth, self.in_buffer.buffer_size, self.in_buffer.format)?;
+            in_buffer.endian = self.in_buffer.endian;
+            in_buffer.speakers = self.in_buffer.speakers;
+            in_buffer.sample_rate = self.in_buffer.sample_rate;
+            self.in_buffer = in_buffer;
+        }
+        self.in_buffer.bytes_mut().copy_from_slice(&buffer[1..]);
+        self.in_buffer.position = Some(self.in_position);
+        // The block arrives after its last frame, so its first frame was a block duration earlier.
+        let now = host_time();
+        self.in_buffer.timestamp = Some(self.in_buffer.duration().and_then(|duration| now.checked_sub(duration)).unwrap_or(now));
+        self.in_position += self.in_buffer.buffer_size as u64;
+        Ok(true)
     }
-    fn write(&mut self)
+    fn write(&mut self) -> Result<()>
     {
-        let mut data = vec![0; self.out_buffer.buffer_size as usize + 1];
-        for index in 1..data.len() { data[index] = unsafe { *self.out_buffer.data.offset(index as isize) }; }
-        let _ = std::io::Write::write(&mut self.writer, &data);
+        let mut data = vec![0; self.out_buffer.real_size() as usize + 1];
+        data[1..].copy_from_slice(self.out_buffer.bytes());
+        std::io::Write::write_all(&mut self.writer, &data)?;
+        self.out_position += self.out_buffer.buffer_size as u64;
+        self.out_buffer.position = Some(self.out_position);
         self.out_buffer.clear();
+        Ok(())
     }
     /// Play until state is false.
-    pub fn play(&mut self, state : &bool)
+    ///
+    /// The first block with samples fades in and the block read after state turns false fades out as the last one.
+    /// Returns early without an error when the input stream ends.
+    pub fn play(&mut self, state : &std::sync::atomic::AtomicBool) -> Result<()>
     {
-        while *state
+        let mut first = true;
+        loop
         {
             let start_time = std::time::Instant::now();
 
-            self.read();
-            self.write();
+            // State is checked before the block is read, so a stop during the sleep still fades out the next block.
+            let last = !state.load(std::sync::atomic::Ordering::Acquire);
+            let Some(samples) = self.read()? else { break; };
+            // Control packets carry no samples, so a silent block is sent in their place.
+            if samples
+            {
+                if let Some(meter) = &self.in_meter { measure(meter, &self.in_buffer, Meter::process); }
+                if let Some(meter) = &self.in_loudness { measure(meter, &self.in_buffer, LoudnessMeter::process); }
+                self.pass()?;
+            }
+            if let Some(envelope) = &mut self.envelope
+            {
+                self.out_buffer.apply_envelope(envelope)?;
+                envelope.discard_before(self.out_position);
+            }
+            if first && self.out_buffer.real_size() != 0
+            {
+                self.out_buffer.fade_in(self.fade.0, self.fade.1)?;
+                first = false;
+            }
+            if last { self.out_buffer.fade_out(self.fade.0, self.fade.1)?; }
+            if let Some(meter) = &self.out_meter { measure(meter, &self.out_buffer, Meter::process); }
+            if let Some(meter) = &self.out_loudness { measure(meter, &self.out_buffer, LoudnessMeter::process); }
+            self.write()?;
+            if last { break; }
 
+            if self.sample_rate == 0 { continue; }
             let elapsed_time = start_time.elapsed();
             let duration = std::time::Duration::from_secs_f64(self.in_buffer.buffer_size as f64 / self.sample_rate as f64);
 
             if elapsed_time >= duration { continue; }
             else { std::thread::sleep(duration - elapsed_time); }
         }
+        Ok(())
     }
 }
+/// Measure a block which has samples at its sample rate, or the default one without it, skipping a meter which panicked
+/// while locked.
+fn measure<M>(meter : &std::sync::Mutex<M>, buffer : &AudioBuffer, process : fn(&mut M, &AudioBuffer, u32))
+{
+    if buffer.container == 0 { return; }
+    if let Ok(mut meter) = meter.lock() { process(&mut meter, buffer, buffer.sample_rate.unwrap_or(DEFAULT_RATE)); }
+}
 unsafe impl<R : std::io::Read, W : std::io::Write> Sync for AudioDevice<R, W> { }
-unsafe impl<R : std::io::Read, W : std::io::Write> Send for AudioDevice<R, W> { }
\ No newline at end of file
+unsafe impl<R : std::io::Read, W : std::io::Write> Send for AudioDevice<R, W> { }
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Create a buffer of 2 channels and 8 frames with the given encoding.
+    fn buffer(format : SampleFormat, bit_depth : u32, container : u32, justify : Justify, endian : Endian, layout : Layout) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init_with_format(2, bit_depth, 8, format).unwrap();
+        if format != SampleFormat::Float { buffer.set_container(container, justify).unwrap(); }
+        buffer.set_endian(endian);
+        buffer.set_layout(layout);
+        buffer
+    }
+    /// Get samples on the grid of the bit depth, covering both ends of the range.
+    fn grid(bit_depth : u32) -> Vec<f64>
+    {
+        let step = 1.0 / (1_i64 << (bit_depth - 1)) as f64;
+        [-1.0, -0.5, -step, 0.0, step, 3.0 * step, 0.25, 0.5, 0.75 - step, 1.0 - step, -0.75, -1.0 + step, 0.125, -0.125, 1.0 - 2.0 * step, -3.0 * step]
+            .to_vec()
+    }
+    /// Check that per-sample and batch writes store the same bytes and read back every sample unchanged.
+    fn round_trip(format : SampleFormat, bit_depth : u32, container : u32, justify : Justify, endian : Endian)
+    {
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            // Floats hold a 24-bit grid exactly in either width.
+            let samples = grid(if format == SampleFormat::Float { 24 } else { bit_depth });
+            let (mut single, mut batch) = (buffer(format, bit_depth, container, justify, endian, layout), buffer(format, bit_depth, container, justify, endian, layout));
+            for (index, sample) in samples.iter().enumerate() { single.write_sample(index as u32 % 2, index as u32 / 2, *sample); }
+            batch.write_slice_as(&samples).unwrap();
+            assert_eq!(single.bytes(), batch.bytes(), "{format:?} {bit_depth} in {container} {justify:?} {endian:?} {layout:?}");
+            let mut read = vec![0.0; samples.len()];
+            batch.read_slice_as(&mut read).unwrap();
+            assert_eq!(read, samples, "{format:?} {bit_depth} in {container} {justify:?} {endian:?} {layout:?}");
+            let single : Vec<f64> = (0..samples.len() as u32).map(|index| single.read_sample(index % 2, index / 2)).collect();
+            assert_eq!(single, samples, "{format:?} {bit_depth} in {container} {justify:?} {endian:?} {layout:?}");
+        }
+    }
+    /// Get the bytes of the first sample after writing a value.
+    fn first_bytes(mut buffer : AudioBuffer, value : f64) -> Vec<u8>
+    {
+        buffer.write_sample(0, 0, value);
+        buffer.bytes()[..buffer.sample_size()].to_vec()
+    }
+
+    #[test]
+    fn containers_round_trip()
+    {
+        for endian in [Endian::Little, Endian::Big]
+        {
+            for justify in [Justify::Left, Justify::Right]
+            {
+                round_trip(SampleFormat::Int, 24, 32, justify, endian);
+                round_trip(SampleFormat::Int, 20, 24, justify, endian);
+                round_trip(SampleFormat::Int, 12, 16, justify, endian);
+                round_trip(SampleFormat::Int, 20, 32, justify, endian);
+            }
+            round_trip(SampleFormat::Int, 16, 16, Justify::Left, endian);
+            round_trip(SampleFormat::Int, 32, 32, Justify::Left, endian);
+        }
+    }
+    #[test]
+    fn offset_binary_round_trip()
+    {
+        for endian in [Endian::Little, Endian::Big]
+        {
+            for bit_depth in [8, 16, 24] { round_trip(SampleFormat::UInt, bit_depth, bit_depth, Justify::Left, endian); }
+            round_trip(SampleFormat::UInt, 12, 16, Justify::Right, endian);
+        }
+    }
+    #[test]
+    fn float_round_trip()
+    {
+        for endian in [Endian::Little, Endian::Big]
+        {
+            round_trip(SampleFormat::Float, 32, 32, Justify::Left, endian);
+            round_trip(SampleFormat::Float, 64, 64, Justify::Left, endian);
+        }
+    }
+    #[test]
+    fn native_slices_round_trip()
+    {
+        for endian in [Endian::Little, Endian::Big]
+        {
+            for layout in [Layout::Interleaved, Layout::Planar]
+            {
+                // Evenly spaced from the smallest to the largest value.
+                let samples : Vec<i16> = (0..16).map(|index| (i16::MIN as i32 + index * 4369) as i16).collect();
+                let mut short = buffer(SampleFormat::Int, 16, 16, Justify::Left, endian, layout);
+                short.write_slice_as(&samples).unwrap();
+                let mut read = vec![0_i16; samples.len()];
+                short.read_slice_as(&mut read).unwrap();
+                assert_eq!(read, samples, "{endian:?} {layout:?}");
+                assert_eq!(short.read_as::<i16>(1, 0), samples[1], "{endian:?} {layout:?}");
+                let samples : Vec<I24> = (0..16).map(|index| I24::new(I24::MIN.get() + index * 1_118_481)).collect();
+                let mut wide = buffer(SampleFormat::Int, 24, 24, Justify::Left, endian, layout);
+                wide.write_slice_as(&samples).unwrap();
+                let mut read = vec![I24::default(); samples.len()];
+                wide.read_slice_as(&mut read).unwrap();
+                assert_eq!(read, samples, "{endian:?} {layout:?}");
+            }
+        }
+    }
+    #[test]
+    fn indices_wrap_around()
+    {
+        let mut buffer = AudioBuffer::init(2, 16, 3).unwrap();
+        buffer.write(7, 0.5);
+        assert_eq!((buffer.read_sample(1, 0), buffer.read(1), buffer.read(13)), (0.5, 0.5, 0.5));
+        buffer.mix(1, 0.25);
+        assert_eq!(buffer.read(7), 0.75);
+    }
+    #[test]
+    #[should_panic(expected = "Sample is out of range.")]
+    fn empty_buffers_have_no_indices() { AudioBuffer::default().read(0); }
+    #[test]
+    fn container_bits()
+    {
+        let int = |bit_depth, container, justify, endian| buffer(SampleFormat::Int, bit_depth, container, justify, endian, Layout::Interleaved);
+        assert_eq!(first_bytes(int(24, 32, Justify::Left, Endian::Little), 0.5), [0x00, 0x00, 0x00, 0x40]);
+        assert_eq!(first_bytes(int(24, 32, Justify::Right, Endian::Little), 0.5), [0x00, 0x00, 0x40, 0x00]);
+        assert_eq!(first_bytes(int(24, 32, Justify::Right, Endian::Little), -0.5), [0x00, 0x00, 0xC0, 0xFF]);
+        assert_eq!(first_bytes(int(20, 24, Justify::Left, Endian::Little), -1.0), [0x00, 0x00, 0x80]);
+        assert_eq!(first_bytes(int(20, 24, Justify::Right, Endian::Little), -1.0), [0x00, 0x00, 0xF8]);
+        assert_eq!(first_bytes(int(12, 16, Justify::Left, Endian::Little), 0.5), [0x00, 0x40]);
+        assert_eq!(first_bytes(int(12, 16, Justify::Right, Endian::Little), 0.5), [0x00, 0x04]);
+        assert_eq!(first_bytes(int(16, 16, Justify::Left, Endian::Big), 0.5), [0x40, 0x00]);
+        assert_eq!(first_bytes(int(24, 32, Justify::Left, Endian::Big), 0.5), [0x40, 0x00, 0x00, 0x00]);
+        let uint = |bit_depth, endian| buffer(SampleFormat::UInt, bit_depth, bit_depth, Justify::Left, endian, Layout::Interleaved);
+        assert_eq!(first_bytes(uint(8, Endian::Little), 0.0), [0x80]);
+        assert_eq!(first_bytes(uint(8, Endian::Little), -1.0), [0x00]);
+        assert_eq!(first_bytes(uint(8, Endian::Little), 1.0), [0xFF]);
+        assert_eq!(first_bytes(uint(16, Endian::Little), 0.0), [0x00, 0x80]);
+        assert_eq!(first_bytes(uint(16, Endian::Big), 0.0), [0x80, 0x00]);
+        assert_eq!(first_bytes(uint(24, Endian::Little), -1.0), [0x00, 0x00, 0x00]);
+    }
+    #[test]
+    fn container_change_keeps_samples()
+    {
+        let samples = grid(20);
+        let mut buffer = buffer(SampleFormat::Int, 20, 24, Justify::Left, Endian::Little, Layout::Interleaved);
+        buffer.write_slice(&samples).unwrap();
+        for (container, justify) in [(32, Justify::Right), (24, Justify::Right), (32, Justify::Left)]
+        {
+            buffer.set_container(container, justify).unwrap();
+            let mut read = vec![0.0; samples.len()];
+            buffer.read_slice(&mut read).unwrap();
+            assert_eq!(read, samples, "{container} {justify:?}");
+        }
+    }
+    #[test]
+    fn clip_policies_match_per_sample_writes()
+    {
+        let loud = [1.5, 0.25, -2.0, -1.0, 0.5, 1.0];
+        let read = |buffer : &AudioBuffer| { let mut read = [0.0; 6]; buffer.read_slice(&mut read).unwrap(); read };
+        for (clip, expected) in [(ClipPolicy::Saturate, [32767.0 / 32768.0, 0.25, -1.0, -1.0, 0.5, 32767.0 / 32768.0]), (ClipPolicy::Wrap, [-0.5, 0.25, 0.0, -1.0, 0.5, -1.0])]
+        {
+            for layout in [Layout::Interleaved, Layout::Planar]
+            {
+                let mut batch = AudioBuffer::init(2, 16, 3).unwrap();
+                batch.set_layout(layout);
+                batch.set_clip_policy(clip);
+                let mut single = batch.clone();
+                batch.write_slice(&loud).unwrap();
+                for (index, sample) in loud.iter().enumerate() { single.write(index as u32, *sample); }
+                assert_eq!(read(&batch), expected, "{clip:?} {layout:?}");
+                assert_eq!(batch.bytes(), single.bytes(), "{clip:?} {layout:?}");
+                assert_eq!((batch.clip_count(), single.clip_count()), (3, 3), "{clip:?} {layout:?}");
+            }
+        }
+    }
+    #[test]
+    fn mix_adds_where_write_replaces()
+    {
+        let mut buffer = AudioBuffer::init(1, 16, 4).unwrap();
+        buffer.write_slice(&[0.25, -0.5, 0.75, 0.0]).unwrap();
+        buffer.mix_slice(&[0.25, 0.25, 0.5, -0.125]).unwrap();
+        let mut read = [0.0; 4];
+        buffer.read_slice(&mut read).unwrap();
+        assert_eq!(read, [0.5, -0.25, 32767.0 / 32768.0, -0.125]);
+        assert_eq!(buffer.clip_count(), 1);
+        buffer.mix(0, 0.125);
+        assert_eq!(buffer.read_sample(0, 0), 0.625);
+        buffer.write(0, 0.125);
+        assert_eq!(buffer.read_sample(0, 0), 0.125);
+        buffer.set_clip_policy(ClipPolicy::Error);
+        assert!(matches!(buffer.mix_slice(&[0.25, 0.0, 0.5, 0.0]), Err(Error::Clipped { channel: 0, frame: 2 })));
+        buffer.read_slice(&mut read).unwrap();
+        assert_eq!(read, [0.375, -0.25, 32767.0 / 32768.0, -0.125]);
+        assert_eq!(buffer.clip_count(), 2);
+    }
+    #[test]
+    fn rejected_writes_stop_alike_on_buffers_and_views()
+    {
+        let samples = [0.25, -0.25, 0.5, 1.5, 0.75, -0.75];
+        let rejecting = |layout|
+        {
+            let mut buffer = AudioBuffer::init(2, 16, 3).unwrap();
+            buffer.set_layout(layout);
+            buffer.set_clip_policy(ClipPolicy::Error);
+            buffer
+        };
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            // Frame by frame, channel 1 of frame 1 is the first rejected sample.
+            let expected = [0.25, -0.25, 0.5, 0.0, 0.0, 0.0];
+            let (mut buffer, mut viewed) = (rejecting(layout), rejecting(layout));
+            assert!(matches!(buffer.write_slice(&samples), Err(Error::Clipped { channel: 1, frame: 1 })));
+            let mut view = viewed.view_mut(0..3, 0..2).unwrap();
+            assert!(matches!(AudioWrite::write_slice(&mut view, &samples), Err(Error::Clipped { channel: 1, frame: 1 })));
+            assert_eq!(buffer.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+            assert_eq!(viewed.samples().collect::<Vec<_>>(), expected, "{layout:?}");
+            let (mut buffer, mut viewed) = (rejecting(layout), rejecting(layout));
+            assert!(matches!(buffer.mix_slice(&samples), Err(Error::Clipped { channel: 1, frame: 1 })));
+            assert!(matches!(viewed.view_mut(0..3, 0..2).unwrap().mix_slice(&samples), Err(Error::Clipped { channel: 1, frame: 1 })));
+            assert_eq!((buffer.samples().collect::<Vec<_>>(), viewed.samples().collect::<Vec<_>>()), (expected.to_vec(), expected.to_vec()), "{layout:?}");
+            let (mut buffer, mut viewed) = (rejecting(layout), rejecting(layout));
+            assert!(matches!(buffer.write_frame(1, &[0.5, 1.5]), Err(Error::Clipped { channel: 1, frame: 1 })));
+            assert!(matches!(viewed.view_mut(1..2, 0..2).unwrap().write_frame(0, &[0.5, 1.5]), Err(Error::Clipped { channel: 1, frame: 0 })));
+            assert!(matches!(buffer.write_channel(0, &[0.5, 2.0, 0.5]), Err(Error::Clipped { channel: 0, frame: 1 })));
+            assert!(matches!(viewed.view_mut(0..3, 0..1).unwrap().write_channel(0, &[0.5, 2.0, 0.5]), Err(Error::Clipped { channel: 0, frame: 1 })));
+            let expected = [0.5, 0.0, 0.5, 0.0, 0.0, 0.0];
+            assert_eq!((buffer.samples().collect::<Vec<_>>(), viewed.samples().collect::<Vec<_>>()), (expected.to_vec(), expected.to_vec()), "{layout:?}");
+            // Conversions write frame by frame as well.
+            let mut source = AudioBuffer::init_with_format(2, 32, 3, SampleFormat::Float).unwrap();
+            source.write_slice(&samples).unwrap();
+            let mut destination = rejecting(layout);
+            assert!(matches!(source.convert_into(&mut destination), Err(Error::Clipped { channel: 1, frame: 1 })));
+            assert_eq!(destination.samples().collect::<Vec<_>>(), [0.25, -0.25, 0.5, 0.0, 0.0, 0.0], "{layout:?}");
+        }
+    }
+    /// Create an empty buffer with the given timing.
+    fn timed(sample_rate : Option<u32>, position : Option<u64>, timestamp : Option<std::time::Duration>) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init(1, 16, 480).unwrap();
+        buffer.set_sample_rate(sample_rate);
+        buffer.set_position(position);
+        buffer.set_timestamp(timestamp);
+        buffer
+    }
+
+    #[test]
+    fn frames_and_durations_convert()
+    {
+        use std::time::Duration;
+        let (cd, dat) = (timed(Some(44100), None, None), timed(Some(48000), None, None));
+        assert_eq!(cd.frames_to_duration(44100), Some(Duration::from_secs(1)));
+        assert_eq!(cd.frames_to_duration(441), Some(Duration::from_millis(10)));
+        assert_eq!(cd.frames_to_duration(1), Some(Duration::from_nanos(22675)));
+        assert_eq!(dat.frames_to_duration(1), Some(Duration::from_nanos(20833)));
+        assert_eq!(dat.duration(), Some(Duration::from_millis(10)));
+        assert_eq!(dat.duration_to_frames(Duration::from_millis(10)), Some(480));
+        assert_eq!(cd.duration_to_frames(Duration::from_secs(3)), Some(132300));
+        // Half a frame rounds up, anything less rounds down.
+        assert_eq!(dat.duration_to_frames(Duration::from_nanos(31250)), Some(2));
+        assert_eq!(dat.duration_to_frames(Duration::from_nanos(31249)), Some(1));
+        assert_eq!(cd.duration_to_frames(Duration::from_millis(5)), Some(221));
+        assert_eq!(cd.duration_to_frames(Duration::from_nanos(4_999_999)), Some(220));
+        for buffer in [timed(None, None, None), timed(Some(0), None, None)]
+        {
+            assert_eq!((buffer.frames_to_duration(1), buffer.duration_to_frames(Duration::from_secs(1))), (None, None));
+        }
+    }
+    #[test]
+    fn frames_align_by_timestamp_or_position()
+    {
+        use std::time::Duration;
+        let second = Duration::from_secs(1);
+        let buffer = timed(Some(48000), Some(1000), Some(second));
+        assert_eq!(buffer.frame_at(second), Some(0));
+        assert_eq!(buffer.frame_at(second + Duration::from_millis(10)), Some(480));
+        assert_eq!(buffer.frame_at(second - Duration::from_millis(10)), Some(-480));
+        assert_eq!(buffer.frame_at(second + Duration::from_nanos(31250)), Some(2));
+        assert_eq!(buffer.frame_at(second - Duration::from_nanos(31250)), Some(-2));
+        assert_eq!(buffer.timestamp_at(480), Some(second + Duration::from_millis(10)));
+        assert_eq!(timed(Some(48000), None, None).frame_at(second), None);
+        assert_eq!(timed(None, None, Some(second)).frame_at(second), None);
+        let cd = timed(Some(44100), None, Some(second));
+        assert_eq!(cd.frame_at(second - Duration::from_millis(5)), Some(-221));
+        // Timestamps win over positions, which only count when one side has no timestamp.
+        assert_eq!(buffer.offset_of(&timed(Some(48000), Some(0), Some(second + Duration::from_millis(20)))), Some(960));
+        assert_eq!(buffer.offset_of(&timed(Some(48000), Some(1480), None)), Some(480));
+        assert_eq!(buffer.offset_of(&timed(Some(48000), Some(900), None)), Some(-100));
+        assert_eq!(buffer.offset_of(&timed(Some(48000), None, None)), None);
+    }
+    /// Fill a buffer with samples numbered by channel and frame.
+    fn numbered(layout : Layout, frames : u32) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init(3, 16, frames).unwrap();
+        buffer.set_layout(layout);
+        for frame in 0..frames
+        {
+            for channel in 0..3 { buffer.write_sample(channel, frame, number(channel, frame)); }
+        }
+        buffer
+    }
+    fn number(channel : u32, frame : u32) -> f64 { (channel * 100 + frame + 1) as f64 / 1024.0 }
+
+    #[test]
+    fn resize_keeps_samples()
+    {
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            let mut buffer = numbered(layout, 10);
+            let capacity = buffer.capacity();
+            buffer.resize(4).unwrap();
+            assert_eq!(buffer.capacity(), capacity);
+            for channel in 0..3
+            {
+                for frame in 0..4 { assert_eq!(buffer.read_sample(channel, frame), number(channel, frame), "{layout:?} shrunk"); }
+            }
+            buffer.resize(10).unwrap();
+            assert_eq!(buffer.capacity(), capacity);
+            for channel in 0..3
+            {
+                for frame in 0..10
+                {
+                    let expected = if frame < 4 { number(channel, frame) } else { 0.0 };
+                    assert_eq!(buffer.read_sample(channel, frame), expected, "{layout:?} grown");
+                }
+            }
+        }
+    }
+    #[test]
+    fn resize_within_capacity_keeps_memory()
+    {
+        let mut buffer = numbered(Layout::Planar, 10);
+        buffer.reserve(90).unwrap();
+        let (capacity, pointer) = (buffer.capacity(), buffer.data.words.as_ptr());
+        assert!(capacity >= 100);
+        buffer.resize(100).unwrap();
+        buffer.resize(1).unwrap();
+        buffer.resize(capacity).unwrap();
+        assert_eq!((buffer.capacity(), buffer.data.words.as_ptr()), (capacity, pointer));
+        assert_eq!(buffer.read_sample(2, 0), number(2, 0));
+    }
+    #[test]
+    fn resize_leaves_clones_alone()
+    {
+        for layout in [Layout::Interleaved, Layout::Planar]
+        {
+            let mut buffer = numbered(layout, 10);
+            let clone = buffer.clone();
+            assert!(buffer.is_shared());
+            buffer.resize(3).unwrap();
+            buffer.resize(12).unwrap();
+            assert!(!buffer.is_shared() && !clone.is_shared());
+            assert_eq!(clone.size(), 10);
+            for channel in 0..3
+            {
+                for frame in 0..10 { assert_eq!(clone.read_sample(channel, frame), number(channel, frame), "{layout:?}"); }
+            }
+        }
+    }
+    #[test]
+    fn writes_leave_clones_alone()
+    {
+        let buffer = numbered(Layout::Planar, 4);
+        let unchanged = |clone : &AudioBuffer| (0..3).all(|channel| (0..4).all(|frame| clone.read_sample(channel, frame) == number(channel, frame)));
+        let writes : [fn(&mut AudioBuffer); 3] =
+        [
+            |target| target.write(5, 0.5),
+            |target| target.write_slice(&[0.5; 12]).unwrap(),
+            |target| for mut sample in target.samples_mut() { sample.set(0.5); }
+        ];
+        for write in writes
+        {
+            let mut target = buffer.clone();
+            assert!(buffer.is_shared() && target.is_shared());
+            write(&mut target);
+            assert!(!buffer.is_shared() && !target.is_shared());
+            assert_eq!(target.read_sample(2, 1), 0.5);
+            assert!(unchanged(&buffer));
+        }
+        let mut copy = buffer.deep_copy();
+        assert!(!buffer.is_shared() && !copy.is_shared());
+        copy.clear();
+        assert!(unchanged(&buffer) && copy.samples().all(|sample| sample == 0.0));
+        let empty = AudioBuffer::default();
+        assert_eq!((empty.channels(), empty.size(), empty.real_size(), empty.is_shared()), (0, 0, 0, false));
+    }
+    #[test]
+    fn buffers_are_send_and_sync()
+    {
+        fn check<T : Send + Sync>() { }
+        check::<AudioBuffer>();
+    }
+    /// Reader which hands out one device packet per read.
+    struct Packets(std::collections::VecDeque<Vec<u8>>);
+    impl std::io::Read for Packets
+    {
+        fn read(&mut self, buffer : &mut [u8]) -> std::io::Result<usize>
+        {
+            let Some(packet) = self.0.pop_front() else { return Ok(0); };
+            buffer[..packet.len()].copy_from_slice(&packet);
+            Ok(packet.len())
+        }
+    }
+    fn device(packets : Vec<Vec<u8>>) -> AudioDevice<Packets, std::io::Sink> { AudioDevice::init("test", Packets(packets.into()), std::io::sink()) }
+
+    /// Reader whose every read fails.
+    struct Broken;
+    impl std::io::Read for Broken
+    {
+        fn read(&mut self, _ : &mut [u8]) -> std::io::Result<usize> { Err(std::io::ErrorKind::BrokenPipe.into()) }
+    }
+
+    #[test]
+    fn bad_buffer_input_returns_errors()
+    {
+        assert!(matches!(AudioBuffer::init(u32::MAX, 32, 2), Err(Error::TooLarge { channels: u32::MAX, frames: 2, container: 32 })));
+        let mut buffer = AudioBuffer::init(2, 16, 4).unwrap();
+        assert!(matches!(buffer.write_slice(&[0.0; 3]), Err(Error::LengthMismatch { expected: 8, actual: 3 })));
+        assert!(matches!(buffer.read_frame(0, &mut [0.0; 3]), Err(Error::LengthMismatch { expected: 2, actual: 3 })));
+        assert!(matches!(buffer.read_channel(2, &mut [0.0; 4]), Err(Error::ChannelOutOfRange { channel: 2, channels: 2 })));
+        assert!(matches!(buffer.write_channel(5, &[0.0; 4]), Err(Error::ChannelOutOfRange { channel: 5, channels: 2 })));
+        assert!(matches!(buffer.view(2..5, 0..2), Err(Error::RangeOutOfBounds { start: 2, end: 5, len: 4 })));
+        assert!(matches!(buffer.view_mut(0..4, 1..3), Err(Error::RangeOutOfBounds { start: 1, end: 3, len: 2 })));
+        assert!(matches!(buffer.set_channel_layout(ChannelLayout::Surround51), Err(Error::InvalidChannelLayout { channels: 2, .. })));
+    }
+    #[test]
+    fn bad_device_input_returns_errors()
+    {
+        let mut device = device(vec![vec![6 << 2], vec![0b11 | (1 << 2)], vec![0b10], vec![0; 4]]);
+        assert!(matches!(device.read(), Err(Error::InvalidFormatCode(6))));
+        for _ in 0..2 { assert_eq!(device.read().unwrap(), Some(false)); }
+        assert!(matches!(device.read(), Err(Error::InvalidPayload { len: 3, samples: 64 })));
+        assert!(matches!(device.set_sample_rate(1000), Err(Error::InvalidSampleRate(1000))));
+        assert!(matches!(device.set_out_channel_layout(ChannelLayout::Discrete(65)), Err(Error::InvalidChannelCount(65))));
+        assert!(matches!(device.set_out_channel_layout(ChannelLayout::Discrete(0)), Err(Error::InvalidChannelCount(0))));
+        assert_eq!(device.get_out_channels(), 0);
+        let error = AudioDevice::init("broken", Broken, std::io::sink()).read().unwrap_err();
+        assert!(matches!(error, Error::Io(_)) && std::error::Error::source(&error).is_some());
+    }
+    #[test]
+    fn device_infers_float_width()
+    {
+        let mut single = vec![0; 1 + 2 * 32 * 4];
+        single[5..9].copy_from_slice(&0.5_f32.to_le_bytes());
+        let mut double = vec![0; 1 + 2 * 32 * 8];
+        double[9..17].copy_from_slice(&(-0.25_f64).to_le_bytes());
+        let mut device = device(vec![vec![SampleFormat::Float.code() << 2], vec![0b11 | (1 << 2)], vec![0b10], single, double]);
+        for _ in 0..3 { assert_eq!(device.read().unwrap(), Some(false)); }
+        assert_eq!(device.read().unwrap(), Some(true));
+        assert_eq!((device.get_in_format(), device.get_in_bit_depth()), (SampleFormat::Float, 32));
+        assert_eq!(device.in_buffer.read_sample(1, 0), 0.5);
+        assert_eq!(device.read().unwrap(), Some(true));
+        assert_eq!((device.get_in_format(), device.get_in_bit_depth()), (SampleFormat::Float, 64));
+        assert_eq!(device.in_buffer.read_sample(1, 0), -0.25);
+    }
+    #[test]
+    fn endian_before_format()
+    {
+        let mut buffer = AudioBuffer::default();
+        buffer.set_endian(Endian::Big);
+        assert_eq!(buffer.endian(), Endian::Big);
+        let mut device = device(Vec::new());
+        device.set_out_endian(Endian::Big).unwrap();
+        device.set_out_format(SampleFormat::Int, 16).unwrap();
+        device.set_out_channel_layout(ChannelLayout::Mono).unwrap();
+        device.out_buffer.resize(1).unwrap();
+        assert_eq!(device.get_out_endian(), Endian::Big);
+        device.out_buffer.write_sample(0, 0, 0.5);
+        assert_eq!(device.out_buffer.bytes(), [0x40, 0x00]);
+    }
+    #[test]
+    fn format_changes_keep_channel_layouts()
+    {
+        let mut device = device(vec![vec![0b11 | (5 << 2)], vec![0b10], vec![0; 1 + 6 * 32 * 2], vec![0; 1 + 6 * 32 * 3]]);
+        device.set_out_channel_layout(ChannelLayout::Surround51).unwrap();
+        device.set_out_format(SampleFormat::Float, 32).unwrap();
+        assert_eq!(device.get_out_channel_layout(), ChannelLayout::Surround51);
+        assert_eq!(device.read().unwrap(), Some(false));
+        device.set_in_channel_layout(ChannelLayout::Surround51).unwrap();
+        assert_eq!(device.read().unwrap(), Some(false));
+        assert_eq!(device.read().unwrap(), Some(true));
+        assert_eq!((device.get_in_bit_depth(), device.get_in_channel_layout()), (16, ChannelLayout::Surround51));
+        assert_eq!(device.read().unwrap(), Some(true));
+        assert_eq!((device.get_in_bit_depth(), device.get_in_channel_layout()), (24, ChannelLayout::Surround51));
+    }
+
+    #[test]
+    fn remix_matches_matrix()
+    {
+        // Few channels mix interleaved blocks on the stack, too many for one frame mix channel by channel.
+        for inputs in [6, MIX_SAMPLES as u32 + 1]
+        {
+            let mut source = AudioBuffer::init_with_format(inputs, 32, 700, SampleFormat::Float).unwrap();
+            source.set_layout(Layout::Planar);
+            for channel in 0..inputs
+            {
+                for frame in 0..700 { source.write_sample(channel, frame, ((channel + frame) % 7) as f64 / 8.0); }
+            }
+            let gains : Vec<f64> = (0..inputs * 2).map(|index| (index % 5) as f64 / 4.0).collect();
+            let matrix = MixMatrix::from_gains(inputs, 2, &gains).unwrap();
+            let mut destination = AudioBuffer::init_with_format(2, 32, 1, SampleFormat::Float).unwrap();
+            destination.write_sample(0, 0, 1.0);
+            source.remix_into(&mut destination, &matrix).unwrap();
+            assert_eq!(destination.size(), 700);
+            for output in 0..2
+            {
+                for frame in [0, 1, 340, 341, 699]
+                {
+                    let expected : f64 = (0..inputs).map(|input| gains[(output * inputs + input) as usize] * source.read_sample(input, frame)).sum();
+                    assert!((destination.read_sample(output, frame) - expected).abs() < 1e-3, "{inputs} inputs, output {output}, frame {frame}");
+                }
+            }
+        }
+    }
+    #[test]
+    fn views_mix_in_place()
+    {
+        let source = numbered(Layout::Planar, 10);
+        let mut destination = AudioBuffer::init(3, 24, 10).unwrap();
+        destination.write_sample(0, 4, 0.25);
+        destination.view_mut(4..8, 0..2).unwrap().add(&source.view(2..6, 1..3).unwrap()).unwrap();
+        for channel in 0..3
+        {
+            for frame in 0..10
+            {
+                let mixed = channel < 2 && (4..8).contains(&frame);
+                let expected = if mixed { number(channel + 1, frame - 2) } else { 0.0 } + if (channel, frame) == (0, 4) { 0.25 } else { 0.0 };
+                assert!((destination.read_sample(channel, frame) - expected).abs() < 1e-6, "channel {channel}, frame {frame}");
+            }
+        }
+        destination.set_clip_policy(ClipPolicy::Error);
+        let mut loud = AudioBuffer::init_with_format(2, 32, 4, SampleFormat::Float).unwrap();
+        loud.write_sample(1, 3, 2.0);
+        let error = destination.view_mut(4..8, 1..3).unwrap().add(&loud);
+        assert!(matches!(error, Err(Error::Clipped { channel: 1, frame: 3 })), "{error:?}");
+    }
+    #[test]
+    fn gains_apply_to_views()
+    {
+        let mut buffer = numbered(Layout::Interleaved, 10);
+        buffer.view_mut(2..6, 1..3).unwrap().scale(0.5).unwrap();
+        buffer.view_mut(0..4, 0..1).unwrap().apply_gain_ramp(0.0, 1.0).unwrap();
+        buffer.apply_gain_ramp(1.0, 1.0).unwrap();
+        for channel in 0..3
+        {
+            for frame in 0..10
+            {
+                let gain = match (channel, frame) { (0, 0..=3) => frame as f64 / 4.0, (1..=2, 2..=5) => 0.5, _ => 1.0 };
+                assert!((buffer.read_sample(channel, frame) - number(channel, frame) * gain).abs() < 1e-4, "channel {channel}, frame {frame}");
+            }
+        }
+        // The first two frames of the view were halved above, so the first one to clip is the third.
+        buffer.set_clip_policy(ClipPolicy::Error);
+        let error = buffer.view_mut(4..10, 2..3).unwrap().scale(8.0);
+        assert!(matches!(error, Err(Error::Clipped { channel: 0, frame: 2 })), "{error:?}");
+    }
+}
@@ -0,0 +1,378 @@
+//! Loudness measurement of ITU-R BS.1770-4 and EBU R 128.
+
+use crate::{AudioRead, ChannelLayout, ChannelPosition, BATCH_FRAMES};
+
+/// Steps of 100 ms in a momentary block.
+const MOMENTARY : usize = 4;
+/// Steps of 100 ms in a short-term block.
+const SHORT_TERM : usize = 30;
+/// Absolute gate of integrated loudness and loudness range in LUFS.
+const ABSOLUTE_GATE : f64 = -70.0;
+/// Relative gate of integrated loudness in LU.
+const RELATIVE_GATE : f64 = -10.0;
+/// Relative gate of loudness range in LU.
+const RANGE_GATE : f64 = -20.0;
+/// Histogram bins of 0.1 LU from the absolute gate up to +30 LUFS.
+const BINS : usize = 1000;
+
+/// Loudness meter with momentary, short-term and gated integrated loudness and loudness range.
+///
+/// Every channel is K-weighted and weighted by its position in the channel layout of the buffers: front and center
+/// channels count fully, side surrounds by +1.5 dB, the LFE is left out and other channels count fully. Momentary
+/// loudness spans 400 ms and short-term loudness 3 s, both moving in steps of 100 ms. Integrated loudness gates the
+/// momentary blocks at -70 LUFS and then 10 LU below their mean, and loudness range takes the spread from the 10th to
+/// the 95th percentile of the short-term loudness gated at -70 LUFS and 20 LU below its mean.
+///
+/// The meter follows the sample rate and channels of the measured blocks, which may be buffers or views, and starts
+/// over when either changes. The blocks for the integrated loudness and range are kept in histograms of 0.1 LU, so
+/// measuring never allocates after the first block.
+#[derive(Clone, Debug)]
+pub struct LoudnessMeter
+{
+    sample_rate : u32,
+    speakers : ChannelLayout,
+    step : usize,
+    filled : usize,
+    weights : Vec<f64>,
+    energies : Vec<f64>,
+    filters : Vec<KWeighting>,
+    steps : [f64; SHORT_TERM],
+    count : usize,
+    max_momentary : f64,
+    max_short_term : f64,
+    momentary : Histogram,
+    short_term : Histogram
+}
+impl Default for LoudnessMeter
+{
+    fn default() -> Self { Self::init() }
+}
+impl LoudnessMeter
+{
+    /// Create an empty meter.
+    pub fn init() -> Self
+    {
+        Self
+        {
+            sample_rate: 0,
+            speakers: ChannelLayout::default(),
+            step: 0,
+            filled: 0,
+            weights: Vec::new(),
+            energies: Vec::new(),
+            filters: Vec::new(),
+            steps: [0.0; SHORT_TERM],
+            count: 0,
+            max_momentary: 0.0,
+            max_short_term: 0.0,
+            momentary: Histogram::default(),
+            short_term: Histogram::default()
+        }
+    }
+    /// Forget every measurement.
+    pub fn reset(&mut self) { *self = Self::init(); }
+    /// Measure the next block of the stream at the given sample rate.
+    pub fn process<A : AudioRead + ?Sized>(&mut self, block : &A, sample_rate : u32)
+    {
+        let (sample_rate, channels, speakers) = (sample_rate.max(1), block.channels(), block.channel_layout());
+        if sample_rate != self.sample_rate || channels as usize != self.weights.len() { self.restart(sample_rate, channels); }
+        if speakers != self.speakers
+        {
+            self.speakers = speakers;
+            for (channel, weight) in self.weights.iter_mut().enumerate() { *weight = weight_of(speakers.position(channel as u32)); }
+        }
+        let frames = block.size() as usize;
+        let mut samples = [0.0; BATCH_FRAMES];
+        let mut first = 0;
+        while first < frames
+        {
+            let len = (frames - first).min(self.step - self.filled);
+            for (channel, (filter, energy)) in self.filters.iter_mut().zip(&mut self.energies).enumerate()
+            {
+                for start in (first..first + len).step_by(BATCH_FRAMES)
+                {
+                    let samples = &mut samples[..BATCH_FRAMES.min(first + len - start)];
+                    block.read_channel_at(channel as u32, start as u32, samples).expect("Block lies inside the buffer.");
+                    *energy += samples.iter().map(|&sample| filter.process(sample).powi(2)).sum::<f64>();
+                }
+            }
+            first += len;
+            self.filled += len;
+            if self.filled == self.step { self.close_step(); }
+        }
+    }
+    /// Get the loudness of the last 400 ms in LUFS, negative infinity before the first 400 ms.
+    pub fn momentary(&self) -> f64
+    {
+        if self.count < MOMENTARY { return f64::NEG_INFINITY; }
+        lufs(self.steps[SHORT_TERM - MOMENTARY..].iter().sum::<f64>() / MOMENTARY as f64)
+    }
+    /// Get the loudness of the last 3 s in LUFS, negative infinity before the first 3 s.
+    pub fn short_term(&self) -> f64
+    {
+        if self.count < SHORT_TERM { return f64::NEG_INFINITY; }
+        lufs(self.steps.iter().sum::<f64>() / SHORT_TERM as f64)
+    }
+    /// Get the highest momentary loudness so far in LUFS.
+    pub fn max_momentary(&self) -> f64 { lufs(self.max_momentary) }
+    /// Get the highest short-term loudness so far in LUFS.
+    pub fn max_short_term(&self) -> f64 { lufs(self.max_short_term) }
+    /// Get the gated loudness of everything measured so far in LUFS, negative infinity while everything is gated.
+    pub fn integrated(&self) -> f64
+    {
+        let (count, sum) = self.momentary.gated(RELATIVE_GATE).fold((0, 0.0), |(count, sum), bin| (count + bin.0, sum + bin.1));
+        lufs(if count == 0 { 0.0 } else { sum / count as f64 })
+    }
+    /// Get the loudness range of everything measured so far in LU.
+    pub fn loudness_range(&self) -> f64
+    {
+        let count = self.short_term.gated(RANGE_GATE).map(|bin| bin.0).sum::<u64>();
+        if count == 0 { return 0.0; }
+        let percentile = |fraction : f64|
+        {
+            let mut rank = ((count - 1) as f64 * fraction).round() as u64;
+            let bin = self.short_term.gated(RANGE_GATE).find(|bin| { if rank < bin.0 { return true; } rank -= bin.0; false });
+            bin.map_or(f64::NEG_INFINITY, |bin| lufs(bin.1 / bin.0 as f64))
+        };
+        percentile(0.95) - percentile(0.10)
+    }
+    /// Measure every block of a WAVE file.
+    pub fn process_wave<R : std::io::Read>(&mut self, reader : &mut crate::WaveReader<R>) -> crate::Result<()>
+    {
+        while let Some(buffer) = reader.read_block(reader.sample_rate().max(1))? { self.process(&buffer, reader.sample_rate()); }
+        Ok(())
+    }
+    fn restart(&mut self, sample_rate : u32, channels : u32)
+    {
+        *self = Self::init();
+        self.sample_rate = sample_rate;
+        self.step = (sample_rate as usize / 10).max(1);
+        self.weights = vec![1.0; channels as usize];
+        self.energies = vec![0.0; channels as usize];
+        self.filters = vec![KWeighting::init(sample_rate as f64); channels as usize];
+        self.momentary = Histogram::init();
+        self.short_term = Histogram::init();
+    }
+    /// Store the weighted mean square of the finished step and the blocks ending with it.
+    fn close_step(&mut self)
+    {
+        let power = self.weights.iter().zip(&self.energies).map(|(weight, energy)| weight * energy).sum::<f64>() / self.step as f64;
+        self.energies.fill(0.0);
+        self.filled = 0;
+        self.steps.rotate_left(1);
+        self.steps[SHORT_TERM - 1] = power;
+        self.count += 1;
+        if self.count >= MOMENTARY
+        {
+            let power = self.steps[SHORT_TERM - MOMENTARY..].iter().sum::<f64>() / MOMENTARY as f64;
+            self.max_momentary = self.max_momentary.max(power);
+            self.momentary.add(power);
+        }
+        if self.count >= SHORT_TERM
+        {
+            let power = self.steps.iter().sum::<f64>() / SHORT_TERM as f64;
+            self.max_short_term = self.max_short_term.max(power);
+            self.short_term.add(power);
+        }
+    }
+}
+
+/// Block powers above the absolute gate, counted and summed in bins of 0.1 LU.
+#[derive(Clone, Debug, Default)]
+struct Histogram
+{
+    bins : Vec<(u64, f64)>
+}
+impl Histogram
+{
+    fn init() -> Self { Self { bins: vec![(0, 0.0); BINS] } }
+    fn add(&mut self, block : f64)
+    {
+        if block <= power(ABSOLUTE_GATE) { return; }
+        if let Some(bin) = self.bins.get_mut(index(block))
+        {
+            bin.0 += 1;
+            bin.1 += block;
+        }
+    }
+    /// Iterate over the bins above the relative gate below the mean power of every block.
+    ///
+    /// The bin holding the gate counts when the mean of its blocks lies above the gate.
+    fn gated(&self, relative : f64) -> impl Iterator<Item = &(u64, f64)>
+    {
+        let (count, sum) = self.bins.iter().fold((0, 0.0), |(count, sum), bin| (count + bin.0, sum + bin.1));
+        let mean = if count == 0 { 0.0 } else { sum / count as f64 };
+        let threshold = power(ABSOLUTE_GATE).max(mean * 10_f64.powf(relative / 10.0));
+        let bins = self.bins.get(index(threshold)..).unwrap_or_default();
+        bins.iter().enumerate().filter(move |(offset, bin)| *offset > 0 || bin.1 > threshold * bin.0 as f64).map(|(_, bin)| bin)
+    }
+}
+
+/// Get the histogram bin of a block power.
+fn index(block : f64) -> usize { (((lufs(block) - ABSOLUTE_GATE) * 10.0).max(0.0) as usize).min(BINS - 1) }
+/// Convert a weighted mean square into LUFS.
+fn lufs(power : f64) -> f64 { -0.691 + 10.0 * power.log10() }
+/// Convert LUFS into a weighted mean square.
+fn power(lufs : f64) -> f64 { 10_f64.powf((lufs + 0.691) / 10.0) }
+/// Get the weight of a channel position.
+fn weight_of(position : Option<ChannelPosition>) -> f64
+{
+    match position
+    {
+        Some(ChannelPosition::LowFrequency) => 0.0,
+        Some(ChannelPosition::SideLeft | ChannelPosition::SideRight) => 1.41,
+        _ => 1.0
+    }
+}
+
+/// High shelf and high-pass pre-filter of BS.1770, designed for the sample rate.
+#[derive(Clone, Debug)]
+struct KWeighting
+{
+    stages : [Biquad; 2]
+}
+impl KWeighting
+{
+    fn init(sample_rate : f64) -> Self
+    {
+        let shelf =
+        {
+            let (gain, quality) = (3.999843853973347, 0.7071752369554196);
+            let k = (std::f64::consts::PI * 1681.974450955533 / sample_rate).tan();
+            let (high, band) = (10_f64.powf(gain / 20.0), 10_f64.powf(gain / 20.0 * 0.4996667741545416));
+            let a0 = 1.0 + k / quality + k * k;
+            Biquad::init([(high + band * k / quality + k * k) / a0, 2.0 * (k * k - high) / a0, (high - band * k / quality + k * k) / a0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / quality + k * k) / a0])
+        };
+        let high_pass =
+        {
+            let quality = 0.5003270373238773;
+            let k = (std::f64::consts::PI * 38.13547087602444 / sample_rate).tan();
+            let a0 = 1.0 + k / quality + k * k;
+            Biquad::init([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / quality + k * k) / a0])
+        };
+        Self { stages: [shelf, high_pass] }
+    }
+    #[inline]
+    fn process(&mut self, sample : f64) -> f64 { self.stages.iter_mut().fold(sample, |sample, stage| stage.process(sample)) }
+}
+
+/// Second order section in transposed direct form II.
+#[derive(Clone, Debug)]
+struct Biquad
+{
+    b : [f64; 3],
+    a : [f64; 2],
+    state : [f64; 2]
+}
+impl Biquad
+{
+    fn init(b : [f64; 3], a : [f64; 2]) -> Self { Self { b, a, state: [0.0; 2] } }
+    #[inline]
+    fn process(&mut self, sample : f64) -> f64
+    {
+        let output = self.b[0] * sample + self.state[0];
+        self.state[0] = self.b[1] * sample - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * sample - self.a[1] * output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{AudioBuffer, SampleFormat};
+
+    /// Feed a 1 kHz sine at 48 kHz with a level in dBFS per channel for the given seconds.
+    fn sine(meter : &mut LoudnessMeter, layout : ChannelLayout, levels : &[f64], seconds : f64, start : &mut u64)
+    {
+        let channels = layout.channels();
+        let mut buffer = AudioBuffer::init_with_format(channels, 32, 4800, SampleFormat::Float).unwrap();
+        buffer.set_channel_layout(layout).unwrap();
+        buffer.set_sample_rate(Some(48000));
+        let mut samples = vec![0.0; 4800 * channels as usize];
+        let end = *start + (seconds * 48000.0).round() as u64;
+        while *start < end
+        {
+            let frames = (end - *start).min(4800) as usize;
+            buffer.resize(frames as u32).unwrap();
+            for (frame, samples) in samples[..frames * channels as usize].chunks_mut(channels as usize).enumerate()
+            {
+                let phase = 2.0 * std::f64::consts::PI * 1000.0 * (*start + frame as u64) as f64 / 48000.0;
+                for (sample, level) in samples.iter_mut().zip(levels) { *sample = 10_f64.powf(level / 20.0) * phase.sin(); }
+            }
+            buffer.write_slice_as(&samples[..frames * channels as usize]).unwrap();
+            meter.process(&buffer, 48000);
+            *start += frames as u64;
+        }
+    }
+    /// Measure stereo segments of a level in dBFS and a length in seconds.
+    fn stereo(segments : &[(f64, f64)]) -> LoudnessMeter
+    {
+        let (mut meter, mut start) = (LoudnessMeter::init(), 0);
+        for &(level, seconds) in segments { sine(&mut meter, ChannelLayout::Stereo, &[level, level], seconds, &mut start); }
+        meter
+    }
+    /// Check a measurement against the value pinned for the implementation, well inside the EBU tolerance of 0.1.
+    fn assert_near(value : f64, expected : f64)
+    {
+        assert!((value - expected).abs() < 0.005, "measured {value}, expected {expected}");
+    }
+
+    #[test]
+    fn tech_3341_steady_tones()
+    {
+        let meter = stereo(&[(-23.0, 20.0)]);
+        assert_near(meter.integrated(), -22.99);
+        assert_near(meter.momentary(), -22.99);
+        assert_near(meter.short_term(), -22.99);
+        assert_near(stereo(&[(-33.0, 20.0)]).integrated(), -32.99);
+    }
+    #[test]
+    fn tech_3341_gating()
+    {
+        assert_near(stereo(&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)]).integrated(), -23.014);
+        assert_near(stereo(&[(-72.0, 10.0), (-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0), (-72.0, 10.0)]).integrated(), -23.014);
+        assert_near(stereo(&[(-26.0, 20.0), (-20.0, 20.1), (-26.0, 20.0)]).integrated(), -22.979);
+    }
+    #[test]
+    fn tech_3341_surround()
+    {
+        let (mut meter, mut start) = (LoudnessMeter::init(), 0);
+        sine(&mut meter, ChannelLayout::Surround51, &[-28.0, -28.0, -24.0, f64::NEG_INFINITY, -30.0, -30.0], 20.0, &mut start);
+        assert_near(meter.integrated(), -23.016);
+    }
+    #[test]
+    fn tech_3342_loudness_range()
+    {
+        assert_near(stereo(&[(-20.0, 20.0), (-30.0, 20.0)]).loudness_range(), 10.0);
+        assert_near(stereo(&[(-20.0, 20.0), (-15.0, 20.0)]).loudness_range(), 5.0);
+        assert_near(stereo(&[(-40.0, 20.0), (-20.0, 20.0)]).loudness_range(), 20.0);
+        assert_near(stereo(&[(-50.0, 20.0), (-35.0, 20.0), (-20.0, 20.0), (-35.0, 20.0), (-50.0, 20.0)]).loudness_range(), 15.0);
+    }
+    #[test]
+    fn views_measure_like_buffers()
+    {
+        // A view of the later frames of a surround buffer keeps its layout and measures like the buffer.
+        let levels = [-28.0, -28.0, -24.0, f64::NEG_INFINITY, -30.0, -30.0];
+        let mut meter = LoudnessMeter::init();
+        sine(&mut meter, ChannelLayout::Surround51, &levels, 5.0, &mut 0);
+        let mut buffer = AudioBuffer::init_with_format(6, 32, 9600, SampleFormat::Float).unwrap();
+        buffer.set_channel_layout(ChannelLayout::Surround51).unwrap();
+        let tone = (0..9600).map(|frame| (2.0 * std::f64::consts::PI * 1000.0 * frame as f64 / 48000.0).sin());
+        let samples = tone.flat_map(|sine| levels.map(|level : f64| 10_f64.powf(level / 20.0) * sine)).collect::<Vec<_>>();
+        buffer.write_slice_as(&samples).unwrap();
+        let view = buffer.view(4800..9600, 0..6).unwrap();
+        let mut viewed = LoudnessMeter::init();
+        for _ in 0..50 { viewed.process(&view, 48000); }
+        assert_near(viewed.integrated(), meter.integrated());
+        assert_near(viewed.momentary(), meter.momentary());
+    }
+    #[test]
+    fn silence_is_gated()
+    {
+        let meter = stereo(&[(f64::NEG_INFINITY, 5.0)]);
+        assert_eq!(meter.integrated(), f64::NEG_INFINITY);
+        assert_eq!(meter.loudness_range(), 0.0);
+    }
+}
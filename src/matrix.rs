@@ -0,0 +1,227 @@
+//! Mixing matrices which map the channels of one layout onto another.
+
+use crate::{ChannelLayout, ChannelPosition};
+
+/// Gain of a channel folded into a single neighbouring speaker, -3 dB as in ITU-R BS.775.
+const FOLD : f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Gain law of a mono channel panned between two speakers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanLaw
+{
+    /// Gains sum to one, -6 dB in the center.
+    Linear,
+    /// Powers sum to one, -3 dB in the center.
+    #[default]
+    ConstantPower,
+    /// Geometric mean of the linear and constant power laws, -4.5 dB in the center.
+    Compromise
+}
+
+/// Gains from every input channel to every output channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixMatrix
+{
+    inputs : u32,
+    outputs : u32,
+    gains : Vec<f64>
+}
+impl MixMatrix
+{
+    /// Create a silent matrix.
+    pub fn init(inputs : u32, outputs : u32) -> Self { Self { inputs, outputs, gains: vec![0.0; inputs as usize * outputs as usize] } }
+    /// Create a matrix which passes every channel through.
+    pub fn identity(channels : u32) -> Self
+    {
+        let mut matrix = Self::init(channels, channels);
+        for channel in 0..channels { matrix.gains[(channel * channels + channel) as usize] = 1.0; }
+        matrix
+    }
+    /// Create a matrix from gains in rows of outputs, each holding the gain of every input.
+    pub fn from_gains(inputs : u32, outputs : u32, gains : &[f64]) -> crate::Result<Self>
+    {
+        crate::check_len(inputs as usize * outputs as usize, gains.len())?;
+        Ok(Self { inputs, outputs, gains: gains.to_vec() })
+    }
+    /// Create a matrix which pans mono into stereo, from -1 for left to 1 for right.
+    pub fn pan(pan : f64, law : PanLaw) -> Self
+    {
+        let right = (pan.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        let power = |value : f64| (value * std::f64::consts::FRAC_PI_2).sin();
+        let gain = |value : f64| match law
+        {
+            PanLaw::Linear => value,
+            PanLaw::ConstantPower => power(value),
+            PanLaw::Compromise => (value * power(value)).sqrt()
+        };
+        Self { inputs: 1, outputs: 2, gains: vec![gain(1.0 - right), gain(right)] }
+    }
+    /// Create the standard up or down-mix between two layouts.
+    ///
+    /// Speakers missing from the target fold into their neighbours at -3 dB: the center into left and right, rear into
+    /// side or front and top into the ear level below. This gives the ITU-R BS.775 down-mix of 5.1 into stereo, the
+    /// LFE is dropped. Mono becomes the center or a constant power pan, and stereo becomes mono as the average of left
+    /// and right. Up-mixes keep the shared speakers and leave the others silent.
+    ///
+    /// Layouts without speaker positions copy equal channel counts, copy mono to every channel, average every
+    /// channel into mono and otherwise keep the leading channels.
+    pub fn between(from : ChannelLayout, to : ChannelLayout) -> Self
+    {
+        let (inputs, outputs) = (from.channels(), to.channels());
+        let positional = |layout| !matches!(layout, ChannelLayout::Ambisonic(_) | ChannelLayout::Discrete(_));
+        if from == to { return Self::identity(inputs); }
+        let mut matrix = Self::init(inputs, outputs);
+        if positional(from) && positional(to)
+        {
+            for (input, position) in from.positions().enumerate() { matrix.fold(input as u32, position, 1.0, to); }
+        }
+        else if inputs == 1 { matrix.gains.fill(1.0); }
+        else if outputs == 1 { matrix.gains.fill(1.0 / inputs as f64); }
+        else
+        {
+            for channel in 0..inputs.min(outputs) { matrix.gains[(channel * inputs + channel) as usize] = 1.0; }
+        }
+        matrix
+    }
+    /// Get the number of input channels.
+    pub fn inputs(&self) -> u32 { self.inputs }
+    /// Get the number of output channels.
+    pub fn outputs(&self) -> u32 { self.outputs }
+    /// Get the gain from an input to an output.
+    pub fn gain(&self, output : u32, input : u32) -> Option<f64>
+    {
+        if output >= self.outputs || input >= self.inputs { return None; }
+        Some(self.gains[(output * self.inputs + input) as usize])
+    }
+    /// Set the gain from an input to an output.
+    pub fn set_gain(&mut self, output : u32, input : u32, gain : f64) -> crate::Result<()>
+    {
+        crate::check_channel(output, self.outputs)?;
+        crate::check_channel(input, self.inputs)?;
+        self.gains[(output * self.inputs + input) as usize] = gain;
+        Ok(())
+    }
+    /// Get the gains in rows of outputs.
+    pub fn gains(&self) -> &[f64] { &self.gains }
+    /// Mix interleaved frames of the inputs into interleaved frames of the outputs.
+    pub(crate) fn apply(&self, source : &[f64], target : &mut [f64])
+    {
+        let (inputs, outputs) = (self.inputs as usize, self.outputs as usize);
+        if inputs == 0
+        {
+            target.fill(0.0);
+            return;
+        }
+        if outputs == 0 { return; }
+        for (input, output) in source.chunks_exact(inputs).zip(target.chunks_exact_mut(outputs))
+        {
+            for (sample, row) in output.iter_mut().zip(self.gains.chunks_exact(inputs))
+            {
+                *sample = row.iter().zip(input).map(|(gain, value)| gain * value).sum();
+            }
+        }
+    }
+    fn fold(&mut self, input : u32, position : ChannelPosition, gain : f64, to : ChannelLayout)
+    {
+        use ChannelPosition::*;
+        if let Some(output) = to.channel_of(position)
+        {
+            self.gains[(output * self.inputs + input) as usize] += gain;
+            return;
+        }
+        let has = |position| to.channel_of(position).is_some();
+        match position
+        {
+            FrontLeft | FrontRight if has(FrontCenter) => self.fold(input, FrontCenter, gain / 2.0, to),
+            FrontCenter if has(FrontLeft) =>
+            {
+                self.fold(input, FrontLeft, gain * FOLD, to);
+                self.fold(input, FrontRight, gain * FOLD, to);
+            }
+            SideLeft if has(BackLeft) => self.fold(input, BackLeft, gain, to),
+            SideRight if has(BackRight) => self.fold(input, BackRight, gain, to),
+            BackLeft if has(SideLeft) => self.fold(input, SideLeft, gain * FOLD, to),
+            BackRight if has(SideRight) => self.fold(input, SideRight, gain * FOLD, to),
+            SideLeft | BackLeft | TopFrontLeft => self.fold(input, FrontLeft, gain * FOLD, to),
+            SideRight | BackRight | TopFrontRight => self.fold(input, FrontRight, gain * FOLD, to),
+            TopBackLeft => self.fold(input, SideLeft, gain * FOLD, to),
+            TopBackRight => self.fold(input, SideRight, gain * FOLD, to),
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Check that the gains match the expected rows of outputs within rounding.
+    fn assert_gains(matrix : &MixMatrix, expected : &[f64])
+    {
+        assert_eq!(matrix.gains().len(), expected.len());
+        for (index, (gain, expected)) in matrix.gains().iter().zip(expected).enumerate()
+        {
+            assert!((gain - expected).abs() < 1e-12, "gain {index} is {gain}, expected {expected}");
+        }
+    }
+
+    #[test]
+    fn surround_folds_into_stereo()
+    {
+        // Inputs L, R, C, LFE, Ls, Rs.
+        let matrix = MixMatrix::between(ChannelLayout::Surround51, ChannelLayout::Stereo);
+        assert_gains(&matrix, &[1.0, 0.0, FOLD, 0.0, FOLD, 0.0, 0.0, 1.0, FOLD, 0.0, 0.0, FOLD]);
+        assert!((FOLD - 0.707).abs() < 1e-3);
+        assert_gains(&MixMatrix::between(ChannelLayout::Mono, ChannelLayout::Stereo), &[FOLD, FOLD]);
+        assert_gains(&MixMatrix::between(ChannelLayout::Stereo, ChannelLayout::Mono), &[0.5, 0.5]);
+        assert_gains(&MixMatrix::between(ChannelLayout::Stereo, ChannelLayout::Surround51), &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(MixMatrix::between(ChannelLayout::Surround71, ChannelLayout::Surround71), MixMatrix::identity(8));
+    }
+    #[test]
+    fn heights_fold_to_ear_level()
+    {
+        use ChannelPosition::*;
+        let layout = ChannelLayout::Surround714;
+        let gain = |to : ChannelLayout, output, input| MixMatrix::between(layout, to).gain(to.channel_of(output).unwrap(), layout.channel_of(input).unwrap()).unwrap();
+        for to in [ChannelLayout::Surround71, ChannelLayout::Surround51]
+        {
+            assert_eq!((gain(to, FrontLeft, TopFrontLeft), gain(to, FrontRight, TopFrontRight)), (FOLD, FOLD), "{to:?}");
+            assert_eq!((gain(to, SideLeft, TopBackLeft), gain(to, SideRight, TopBackRight)), (FOLD, FOLD), "{to:?}");
+            assert_eq!((gain(to, FrontLeft, TopBackLeft), gain(to, SideLeft, TopFrontLeft)), (0.0, 0.0), "{to:?}");
+            assert_eq!(gain(to, LowFrequency, LowFrequency), 1.0, "{to:?}");
+        }
+        // Rear speakers join the sides of 5.1 at -3 dB.
+        assert_eq!((gain(ChannelLayout::Surround51, SideLeft, BackLeft), gain(ChannelLayout::Surround51, SideLeft, SideLeft)), (FOLD, 1.0));
+        // Without sides, top rear folds twice on its way to the front.
+        let stereo = ChannelLayout::Stereo;
+        assert!((gain(stereo, FrontLeft, TopBackLeft) - 0.5).abs() < 1e-12);
+        assert_eq!((gain(stereo, FrontLeft, TopFrontLeft), gain(stereo, FrontLeft, BackLeft), gain(stereo, FrontRight, FrontCenter)), (FOLD, FOLD, FOLD));
+        assert_eq!((gain(stereo, FrontLeft, LowFrequency), gain(stereo, FrontRight, TopFrontLeft)), (0.0, 0.0));
+    }
+    #[test]
+    fn pan_laws_meet_in_the_center()
+    {
+        assert_gains(&MixMatrix::pan(0.0, PanLaw::Linear), &[0.5, 0.5]);
+        assert_gains(&MixMatrix::pan(0.0, PanLaw::ConstantPower), &[FOLD, FOLD]);
+        let compromise = (0.5 * FOLD).sqrt();
+        assert_gains(&MixMatrix::pan(0.0, PanLaw::Compromise), &[compromise, compromise]);
+        assert!((20.0 * compromise.log10() + 4.5).abs() < 0.02);
+        for law in [PanLaw::Linear, PanLaw::ConstantPower, PanLaw::Compromise]
+        {
+            assert_gains(&MixMatrix::pan(-1.0, law), &[1.0, 0.0]);
+            assert_gains(&MixMatrix::pan(1.0, law), &[0.0, 1.0]);
+            assert_eq!(MixMatrix::pan(3.0, law), MixMatrix::pan(1.0, law), "{law:?}");
+        }
+        let half = MixMatrix::pan(0.5, PanLaw::ConstantPower);
+        assert!((half.gains()[0].powi(2) + half.gains()[1].powi(2) - 1.0).abs() < 1e-12);
+        assert_gains(&MixMatrix::pan(0.5, PanLaw::Linear), &[0.25, 0.75]);
+    }
+    #[test]
+    fn unpositioned_layouts_copy_or_average()
+    {
+        assert_gains(&MixMatrix::between(ChannelLayout::Mono, ChannelLayout::Discrete(3)), &[1.0, 1.0, 1.0]);
+        assert_gains(&MixMatrix::between(ChannelLayout::Discrete(4), ChannelLayout::Mono), &[0.25; 4]);
+        assert_gains(&MixMatrix::between(ChannelLayout::Discrete(3), ChannelLayout::Stereo), &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+}
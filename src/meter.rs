@@ -0,0 +1,197 @@
+//! Level meters over consecutive AudioBuffers.
+
+use crate::{AudioRead, BATCH_FRAMES};
+
+/// Taps of each phase of the true peak interpolator.
+pub(crate) const TAPS : usize = 12;
+/// Four phase FIR interpolator of ITU-R BS.1770-4 Annex 2.
+pub(crate) const PHASES : [[f64; TAPS]; 4] =
+[
+    [0.0017089843750, 0.0109863281250, -0.0196533203125, 0.0332031250000, -0.0594482421875, 0.1373291015625, 0.9721679687500, -0.1022949218750, 0.0476074218750, -0.0266113281250, 0.0148925781250, -0.0083007812500],
+    [-0.0291748046875, 0.0292968750000, -0.0517578125000, 0.0891113281250, -0.1665039062500, 0.4650878906250, 0.7797851562500, -0.2003173828125, 0.1015625000000, -0.0582275390625, 0.0330810546875, -0.0189208984375],
+    [-0.0189208984375, 0.0330810546875, -0.0582275390625, 0.1015625000000, -0.2003173828125, 0.7797851562500, 0.4650878906250, -0.1665039062500, 0.0891113281250, -0.0517578125000, 0.0292968750000, -0.0291748046875],
+    [-0.0083007812500, 0.0148925781250, -0.0266113281250, 0.0476074218750, -0.1022949218750, 0.9721679687500, 0.1373291015625, -0.0594482421875, 0.0332031250000, -0.0196533203125, 0.0109863281250, 0.0017089843750]
+];
+
+/// Levels of a single channel as linear amplitudes, where 1 is full scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Level
+{
+    peak : f64,
+    rms : f64,
+    true_peak : f64,
+    peak_hold : f64
+}
+impl Level
+{
+    /// Get the highest absolute sample of the last block.
+    pub fn peak(&self) -> f64 { self.peak }
+    /// Get the RMS over the integration time.
+    pub fn rms(&self) -> f64 { self.rms }
+    /// Get the highest absolute value of the last block after 4 times oversampling.
+    pub fn true_peak(&self) -> f64 { self.true_peak }
+    /// Get the held peak, which falls at the decay rate after the hold time.
+    pub fn peak_hold(&self) -> f64 { self.peak_hold }
+    /// Convert a linear amplitude into decibels relative to full scale.
+    pub fn decibels(value : f64) -> f64 { 20.0 * value.log10() }
+}
+
+/// Per-channel sample peak, RMS, true peak and peak hold, updated block by block.
+///
+/// Blocks are buffers or views measured at a given sample rate. The state follows the channel count of the measured
+/// blocks and only allocates when it changes.
+#[derive(Clone, Debug)]
+pub struct Meter
+{
+    hold : std::time::Duration,
+    decay : f64,
+    integration : std::time::Duration,
+    levels : Vec<Level>,
+    channels : Vec<ChannelState>
+}
+#[derive(Clone, Debug, Default)]
+struct ChannelState
+{
+    history : [f64; TAPS - 1],
+    power : f64,
+    held : u64
+}
+impl Default for Meter
+{
+    fn default() -> Self { Self::init() }
+}
+impl Meter
+{
+    /// Create a meter with 300 ms RMS integration, which holds peaks for 1.5 s and then falls at 20 dB/s.
+    pub fn init() -> Self
+    {
+        Self
+        {
+            hold: std::time::Duration::from_millis(1500),
+            decay: 20.0,
+            integration: std::time::Duration::from_millis(300),
+            levels: Vec::new(),
+            channels: Vec::new()
+        }
+    }
+    /// Hold peaks for the given time.
+    pub fn with_hold(self, hold : std::time::Duration) -> Self { Self { hold, ..self } }
+    /// Let held peaks fall by the given decibels per second.
+    pub fn with_decay(self, decay : f64) -> Self { Self { decay, ..self } }
+    /// Integrate the RMS with the given time constant.
+    pub fn with_integration(self, integration : std::time::Duration) -> Self { Self { integration, ..self } }
+    /// Get the levels of every channel.
+    pub fn levels(&self) -> &[Level] { &self.levels }
+    /// Get the levels of a channel.
+    pub fn level(&self, channel : u32) -> Option<Level> { self.levels.get(channel as usize).copied() }
+    /// Forget every level and the filter history.
+    pub fn reset(&mut self)
+    {
+        self.levels.clear();
+        self.channels.clear();
+    }
+    /// Measure the next block of the stream at the given sample rate.
+    pub fn process<A : AudioRead + ?Sized>(&mut self, buffer : &A, sample_rate : u32)
+    {
+        let (channels, frames) = (buffer.channels() as usize, buffer.size() as usize);
+        if self.channels.len() != channels
+        {
+            self.levels.resize(channels, Level::default());
+            self.channels.resize(channels, ChannelState::default());
+        }
+        let rate = sample_rate.max(1) as f64;
+        let smoothing = 1.0 - (-1.0 / (self.integration.as_secs_f64() * rate)).exp();
+        let hold = (self.hold.as_secs_f64() * rate) as u64;
+        let decay = 10_f64.powf(-self.decay * frames as f64 / rate / 20.0);
+        let mut block = [0.0; TAPS - 1 + BATCH_FRAMES];
+        for (channel, (level, state)) in self.levels.iter_mut().zip(&mut self.channels).enumerate()
+        {
+            let (mut peak, mut true_peak) = (0_f64, 0_f64);
+            for first in (0..frames).step_by(BATCH_FRAMES)
+            {
+                let len = BATCH_FRAMES.min(frames - first);
+                block[..TAPS - 1].copy_from_slice(&state.history);
+                buffer.read_channel_at(channel as u32, first as u32, &mut block[TAPS - 1..TAPS - 1 + len]).expect("Block lies inside the buffer.");
+                for &sample in &block[TAPS - 1..TAPS - 1 + len]
+                {
+                    peak = peak.max(sample.abs());
+                    state.power += smoothing * (sample * sample - state.power);
+                }
+                for window in block[..TAPS - 1 + len].windows(TAPS)
+                {
+                    for phase in &PHASES
+                    {
+                        true_peak = true_peak.max(phase.iter().zip(window).map(|(tap, sample)| tap * sample).sum::<f64>().abs());
+                    }
+                }
+                state.history.copy_from_slice(&block[len..TAPS - 1 + len]);
+            }
+            let true_peak = true_peak.max(peak);
+            let held = if state.held > 0 { level.peak_hold } else { level.peak_hold * decay };
+            state.held = state.held.saturating_sub(frames as u64);
+            if peak >= held { state.held = hold; }
+            *level = Level { peak, rms: state.power.sqrt(), true_peak, peak_hold: peak.max(held) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{AudioBuffer, SampleFormat};
+
+    /// Create a mono float block from a function of the frame.
+    fn block(frames : u32, signal : impl Fn(f64) -> f64) -> AudioBuffer
+    {
+        let mut buffer = AudioBuffer::init_with_format(1, 64, frames, SampleFormat::Float).unwrap();
+        for frame in 0..frames { buffer.write_sample(0, frame, signal(frame as f64)); }
+        buffer
+    }
+
+    #[test]
+    fn true_peak_finds_inter_sample_peaks()
+    {
+        // A quarter of the sample rate at 45 degrees only samples the sine at its half power points.
+        let signal = block(4800, |frame| (std::f64::consts::FRAC_PI_2 * frame + std::f64::consts::FRAC_PI_4).sin());
+        let mut meter = Meter::init();
+        meter.process(&signal, 48000);
+        let level = meter.level(0).unwrap();
+        assert!((level.peak() - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-9, "peak {}", level.peak());
+        assert!(Level::decibels(level.true_peak()).abs() < 0.5, "true peak {}", level.true_peak());
+        // Views of the block measure the same.
+        let mut view = Meter::init();
+        view.process(&signal.view(0..4800, 0..1).unwrap(), 48000);
+        assert_eq!(view.level(0), Some(level));
+    }
+    #[test]
+    fn rms_of_sine_settles()
+    {
+        let mut meter = Meter::init();
+        let sine = |start : u32| move |frame : f64| (2.0 * std::f64::consts::PI * 1000.0 * (start as f64 + frame) / 48000.0).sin();
+        for start in (0..96000).step_by(480) { meter.process(&block(480, sine(start)), 48000); }
+        let level = meter.level(0).unwrap();
+        assert!((level.rms() - std::f64::consts::FRAC_1_SQRT_2).abs() < 0.01, "rms {}", level.rms());
+        assert!((level.peak() - 1.0).abs() < 1e-9);
+    }
+    #[test]
+    fn peak_holds_then_decays()
+    {
+        let mut meter = Meter::init().with_hold(std::time::Duration::from_millis(1500)).with_decay(20.0);
+        let silence = block(4800, |_| 0.0);
+        meter.process(&block(4800, |frame| if frame == 100.0 { -1.0 } else { 0.0 }), 48000);
+        for _ in 0..15
+        {
+            meter.process(&silence, 48000);
+            assert_eq!(meter.level(0).unwrap().peak_hold(), 1.0);
+        }
+        // Every further 100 ms block falls by 2 dB.
+        for blocks in 1..=10
+        {
+            meter.process(&silence, 48000);
+            let held = Level::decibels(meter.level(0).unwrap().peak_hold());
+            assert!((held + 2.0 * blocks as f64).abs() < 1e-9, "{held} dB after {blocks} blocks");
+        }
+        assert_eq!(meter.level(0).unwrap().peak(), 0.0);
+    }
+}
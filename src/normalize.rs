@@ -0,0 +1,475 @@
+//! Loudness normalization of finished material.
+
+use crate::meter::{PHASES, TAPS};
+use crate::{AudioBuffer, ChannelLayout, Conversion, Error, Level, LoudnessMeter, Meter, Result, SampleFormat, WaveReader, WaveWriter, DEFAULT_RATE};
+
+/// Frames read from a WAVE file at once.
+const WAVE_BLOCK : u32 = 4096;
+/// Lookahead of the limiter in seconds.
+const LOOKAHEAD : f64 = 0.005;
+/// Release time constant of the limiter in seconds.
+const RELEASE : f64 = 0.1;
+/// Trial passes which raise the gain of a limited normalization.
+const CALIBRATION : usize = 4;
+/// Distance to the loudness target in LU which ends the trial passes.
+const TOLERANCE : f64 = 0.05;
+/// Frames from the newest input of the true peak interpolator to the frame it measures.
+const DETECTION : usize = 5;
+
+/// Control of the true peak after the gain of a normalization.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PeakControl
+{
+    None,
+    Ceiling(f64),
+    Limit(f64)
+}
+
+/// Gain to an integrated loudness target, such as -23 LUFS of EBU R 128 or -14 LUFS of streaming services.
+///
+/// The material is measured as one program by BS.1770, then one gain moves it to the target. A ceiling lowers that
+/// gain until the true peak fits under it, missing the target if needed, while a limiter keeps the gain and only turns
+/// down the frames around peaks above the ceiling, with a lookahead of 5 ms and a release of 100 ms. As limiting lowers
+/// the loudness, up to four trial passes adjust the gain until the limited material is within 0.05 LU of the target.
+/// The outcome reports the loudness and true peak actually reached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normalization
+{
+    target : f64,
+    peak : PeakControl
+}
+impl Normalization
+{
+    /// Create a normalization to a loudness in LUFS without peak control.
+    pub fn init(target : f64) -> Self { Self { target, peak: PeakControl::None } }
+    /// Lower the gain so the true peak stays under the ceiling in dBTP.
+    pub fn with_ceiling(self, ceiling : f64) -> Self { Self { peak: PeakControl::Ceiling(ceiling), ..self } }
+    /// Limit the true peak to the ceiling in dBTP instead of lowering the gain.
+    pub fn with_limiter(self, ceiling : f64) -> Self { Self { peak: PeakControl::Limit(ceiling), ..self } }
+    /// Get the loudness target in LUFS.
+    pub fn target(&self) -> f64 { self.target }
+    /// Get the true peak ceiling in dBTP.
+    pub fn ceiling(&self) -> Option<f64>
+    {
+        match self.peak
+        {
+            PeakControl::None => None,
+            PeakControl::Ceiling(ceiling) | PeakControl::Limit(ceiling) => Some(ceiling)
+        }
+    }
+    /// Check whether peaks above the ceiling are limited.
+    pub fn limits(&self) -> bool { matches!(self.peak, PeakControl::Limit(_)) }
+    /// Normalize consecutive buffers of one program in place, following their clip policy and dither.
+    ///
+    /// Every buffer must have the channel count of the first. Silence is left as it is.
+    pub fn apply(&self, buffers : &mut [AudioBuffer]) -> Result<Normalized>
+    {
+        let Some(channels) = buffers.first().map(|buffer| buffer.channels) else { return Ok(Normalized::silent()); };
+        if let Some(buffer) = buffers.iter().find(|buffer| buffer.channels != channels)
+        {
+            return Err(Error::ChannelMismatch { expected: channels, actual: buffer.channels });
+        }
+        let (normalized, mut stage) = self.plan(&mut |visit| buffers.iter().try_for_each(&mut *visit))?;
+        if normalized.gain == 0.0 && !normalized.limited { return Ok(normalized); }
+        let (mut samples, mut pending, mut target) = (Vec::new(), Vec::new(), 0);
+        for index in 0..buffers.len()
+        {
+            samples.resize((buffers[index].buffer_size * channels) as usize, 0.0);
+            buffers[index].read_slice_as(&mut samples)?;
+            stage.process(&samples, &mut pending);
+            if index + 1 == buffers.len() { stage.flush(&mut pending); }
+            while let Some(buffer) = buffers.get_mut(target)
+            {
+                let len = (buffer.buffer_size * channels) as usize;
+                if pending.len() < len { break; }
+                buffer.write_slice_as(&pending[..len])?;
+                pending.drain(..len);
+                target += 1;
+            }
+        }
+        Ok(normalized)
+    }
+    /// Normalize a WAVE file into another WAVE file with the same sample format and channel layout.
+    ///
+    /// The output is created before the input is read again, so writing over the input is rejected as invalid input.
+    pub fn apply_wave(&self, input : impl AsRef<std::path::Path>, output : impl AsRef<std::path::Path>) -> Result<Normalized>
+    {
+        if std::fs::canonicalize(output.as_ref()).is_ok_and(|output| std::fs::canonicalize(input.as_ref()).is_ok_and(|input| input == output))
+        {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "output is the input file").into());
+        }
+        let open = || -> Result<_> { WaveReader::init(std::io::BufReader::new(std::fs::File::open(input.as_ref())?)) };
+        let (normalized, mut stage) = self.plan(&mut |visit|
+        {
+            let mut reader = open()?;
+            while let Some(buffer) = reader.read_block(WAVE_BLOCK)? { visit(&buffer)?; }
+            Ok(())
+        })?;
+        let mut reader = open()?;
+        let conversion = Conversion::init(reader.format(), reader.bit_depth(), reader.channels()).with_channel_layout(reader.channel_layout());
+        let mut writer = WaveWriter::init(std::io::BufWriter::new(std::fs::File::create(output.as_ref())?), &conversion, reader.sample_rate())?;
+        let mut block = scratch(reader.channels(), reader.channel_layout(), reader.sample_rate())?;
+        let (mut samples, mut pending) = (Vec::new(), Vec::new());
+        while let Some(buffer) = reader.read_block(WAVE_BLOCK)?
+        {
+            samples.resize((buffer.buffer_size * buffer.channels) as usize, 0.0);
+            buffer.read_slice_as(&mut samples)?;
+            stage.process(&samples, &mut pending);
+            fill(&mut block, &mut pending)?;
+            writer.write_block(&block)?;
+        }
+        stage.flush(&mut pending);
+        fill(&mut block, &mut pending)?;
+        writer.write_block(&block)?;
+        writer.finish()?;
+        Ok(normalized)
+    }
+    /// Measure a program and choose the gain and the stage which applies it.
+    ///
+    /// Limiting lowers the loudness, so the gain is adjusted over trial passes until the limited program is within
+    /// tolerance of the target.
+    fn plan(&self, program : Program<'_>) -> Result<(Normalized, Stage)>
+    {
+        let mut measurement = Measurement::init();
+        let mut format = None;
+        program(&mut |buffer|
+        {
+            measurement.process(buffer);
+            format.get_or_insert((buffer.channels, buffer.speakers, buffer.sample_rate.unwrap_or(DEFAULT_RATE)));
+            Ok(())
+        })?;
+        let (loudness, true_peak) = (measurement.loudness.integrated(), Level::decibels(measurement.true_peak));
+        let Some((channels, speakers, sample_rate)) = format.filter(|_| loudness.is_finite()) else
+        {
+            return Ok((Normalized::with_gain(loudness, true_peak, 0.0), Stage::Gain(1.0)));
+        };
+        let mut gain = self.target - loudness;
+        let ceiling = match self.peak
+        {
+            PeakControl::None => return Ok((Normalized::with_gain(loudness, true_peak, gain), Stage::Gain(linear(gain)))),
+            PeakControl::Ceiling(ceiling) =>
+            {
+                gain = gain.min(ceiling - true_peak);
+                return Ok((Normalized::with_gain(loudness, true_peak, gain), Stage::Gain(linear(gain))));
+            }
+            PeakControl::Limit(ceiling) if true_peak + gain <= ceiling => return Ok((Normalized::with_gain(loudness, true_peak, gain), Stage::Gain(linear(gain)))),
+            PeakControl::Limit(ceiling) => linear(ceiling)
+        };
+        let limiter = |gain : f64| Limiter::init(linear(gain), ceiling, channels as usize, sample_rate);
+        let (mut output, mut previous) : (_, Option<(f64, f64)>) = (Measurement::init(), None);
+        for pass in 1..=CALIBRATION
+        {
+            let mut stage = Stage::Limit(Box::new(limiter(gain)));
+            let (mut meter, mut block) = (Measurement::init(), scratch(channels, speakers, sample_rate)?);
+            let (mut samples, mut pending) = (Vec::new(), Vec::new());
+            program(&mut |buffer|
+            {
+                samples.resize((buffer.buffer_size * buffer.channels) as usize, 0.0);
+                buffer.read_slice_as(&mut samples)?;
+                stage.process(&samples, &mut pending);
+                fill(&mut block, &mut pending)?;
+                meter.process(&block);
+                Ok(())
+            })?;
+            stage.flush(&mut pending);
+            fill(&mut block, &mut pending)?;
+            meter.process(&block);
+            output = meter;
+            let reached = output.loudness.integrated();
+            let shortfall = self.target - reached;
+            if !shortfall.is_finite() || shortfall.abs() < TOLERANCE || pass == CALIBRATION { break; }
+            // Harder limiting makes the loudness grow slower than the gain, so step along the slope of the last passes.
+            let slope = previous.map_or(1.0, |(last_gain, last_reached)| ((reached - last_reached) / (gain - last_gain)).clamp(0.1, 1.0));
+            previous = Some((gain, reached));
+            gain += shortfall / slope;
+        }
+        let normalized = Normalized
+        {
+            loudness,
+            true_peak,
+            gain,
+            limited: true,
+            output_loudness: output.loudness.integrated(),
+            output_true_peak: Level::decibels(output.true_peak)
+        };
+        Ok((normalized, Stage::Limit(Box::new(limiter(gain)))))
+    }
+}
+
+/// Visitor of every block of a program, which can run more than once.
+type Program<'a> = &'a mut dyn FnMut(&mut dyn FnMut(&AudioBuffer) -> Result<()>) -> Result<()>;
+
+/// Create an empty buffer for processed samples.
+fn scratch(channels : u32, speakers : ChannelLayout, sample_rate : u32) -> Result<AudioBuffer>
+{
+    let mut block = AudioBuffer::init_with_format(channels, 64, 0, SampleFormat::Float)?;
+    block.set_channel_layout(speakers)?;
+    block.set_sample_rate(Some(sample_rate));
+    Ok(block)
+}
+/// Move interleaved samples into a buffer of their length.
+fn fill(block : &mut AudioBuffer, pending : &mut Vec<f64>) -> Result<()>
+{
+    block.resize((pending.len() / block.channels.max(1) as usize) as u32)?;
+    block.write_slice_as(pending)?;
+    pending.clear();
+    Ok(())
+}
+/// Convert decibels into a linear gain.
+fn linear(decibels : f64) -> f64 { 10_f64.powf(decibels / 20.0) }
+
+/// Outcome of a normalization.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normalized
+{
+    loudness : f64,
+    true_peak : f64,
+    gain : f64,
+    limited : bool,
+    output_loudness : f64,
+    output_true_peak : f64
+}
+impl Normalized
+{
+    /// Get the integrated loudness before the normalization in LUFS.
+    pub fn loudness(&self) -> f64 { self.loudness }
+    /// Get the true peak before the normalization in dBTP.
+    pub fn true_peak(&self) -> f64 { self.true_peak }
+    /// Get the applied gain in dB.
+    pub fn gain(&self) -> f64 { self.gain }
+    /// Check whether the limiter was needed.
+    pub fn limited(&self) -> bool { self.limited }
+    /// Get the integrated loudness after the normalization in LUFS, which misses the target when a ceiling or the
+    /// limiter holds it back.
+    pub fn output_loudness(&self) -> f64 { self.output_loudness }
+    /// Get the true peak after the normalization in dBTP.
+    pub fn output_true_peak(&self) -> f64 { self.output_true_peak }
+    fn with_gain(loudness : f64, true_peak : f64, gain : f64) -> Self
+    {
+        Self { loudness, true_peak, gain, limited: false, output_loudness: loudness + gain, output_true_peak: true_peak + gain }
+    }
+    fn silent() -> Self { Self::with_gain(f64::NEG_INFINITY, f64::NEG_INFINITY, 0.0) }
+}
+
+/// Integrated loudness and highest true peak of a program.
+struct Measurement
+{
+    loudness : LoudnessMeter,
+    meter : Meter,
+    true_peak : f64
+}
+impl Measurement
+{
+    fn init() -> Self { Self { loudness: LoudnessMeter::init(), meter: Meter::init(), true_peak: 0.0 } }
+    fn process(&mut self, buffer : &AudioBuffer)
+    {
+        let sample_rate = buffer.sample_rate.unwrap_or(DEFAULT_RATE);
+        self.loudness.process(buffer, sample_rate);
+        self.meter.process(buffer, sample_rate);
+        self.true_peak = self.meter.levels().iter().map(Level::true_peak).fold(self.true_peak, f64::max);
+    }
+}
+
+/// Processing of interleaved samples, which may hold some back until they can be finished.
+enum Stage
+{
+    Gain(f64),
+    Limit(Box<Limiter>)
+}
+impl Stage
+{
+    fn process(&mut self, samples : &[f64], output : &mut Vec<f64>)
+    {
+        match self
+        {
+            Self::Gain(gain) => output.extend(samples.iter().map(|sample| sample * *gain)),
+            Self::Limit(limiter) => limiter.process(samples, output)
+        }
+    }
+    fn flush(&mut self, output : &mut Vec<f64>)
+    {
+        if let Self::Limit(limiter) = self { limiter.flush(output); }
+    }
+}
+
+/// Lookahead true peak limiter with one gain for every channel.
+///
+/// Each frame needs the gain which brings its oversampled peak down to the ceiling. The gain applied to a frame is the
+/// lowest need within the hold window, averaged over the lookahead so it moves smoothly, and released slowly. As the
+/// hold window is slightly longer than the average, every averaged value covers the needs of the frame and its
+/// neighbours, and the delayed output never exceeds them.
+struct Limiter
+{
+    gain : f64,
+    ceiling : f64,
+    channels : usize,
+    lookahead : usize,
+    release : f64,
+    history : Vec<[f64; TAPS - 1]>,
+    delay : std::collections::VecDeque<f64>,
+    needs : std::collections::VecDeque<(u64, f64)>,
+    holds : std::collections::VecDeque<f64>,
+    sum : f64,
+    current : f64,
+    index : u64
+}
+impl Limiter
+{
+    fn init(gain : f64, ceiling : f64, channels : usize, sample_rate : u32) -> Self
+    {
+        let lookahead = ((sample_rate as f64 * LOOKAHEAD) as usize).max(1);
+        Self
+        {
+            gain,
+            ceiling,
+            channels,
+            lookahead,
+            release: 1.0 - (-1.0 / (RELEASE * sample_rate as f64)).exp(),
+            history: vec![[0.0; TAPS - 1]; channels],
+            delay: std::collections::VecDeque::with_capacity((lookahead + DETECTION + 1) * channels),
+            needs: std::collections::VecDeque::new(),
+            holds: std::collections::VecDeque::with_capacity(lookahead + 1),
+            sum: 0.0,
+            current: 1.0,
+            index: 0
+        }
+    }
+    fn process(&mut self, samples : &[f64], output : &mut Vec<f64>)
+    {
+        if self.channels == 0 { return; }
+        let gain = self.gain;
+        for frame in samples.chunks_exact(self.channels) { self.push(frame.iter().map(|sample| sample * gain), output); }
+    }
+    fn flush(&mut self, output : &mut Vec<f64>)
+    {
+        for _ in 0..self.lookahead + DETECTION { self.push(std::iter::repeat_n(0.0, self.channels), output); }
+    }
+    fn push(&mut self, frame : impl Iterator<Item = f64>, output : &mut Vec<f64>)
+    {
+        let mut peak = 0_f64;
+        for (history, sample) in self.history.iter_mut().zip(frame)
+        {
+            let mut window = [0.0; TAPS];
+            window[..TAPS - 1].copy_from_slice(history);
+            window[TAPS - 1] = sample;
+            peak = peak.max(window[TAPS - 1 - DETECTION].abs());
+            for phase in &PHASES { peak = peak.max(phase.iter().zip(&window).map(|(tap, sample)| tap * sample).sum::<f64>().abs()); }
+            history.copy_from_slice(&window[1..]);
+            self.delay.push_back(sample);
+        }
+        let need = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+        while self.needs.back().is_some_and(|&(_, other)| other >= need) { self.needs.pop_back(); }
+        self.needs.push_back((self.index, need));
+        while self.needs.front().is_some_and(|&(index, _)| index + self.lookahead as u64 + 2 <= self.index) { self.needs.pop_front(); }
+        let hold = self.needs.front().map_or(1.0, |&(_, need)| need);
+        self.holds.push_back(hold);
+        self.sum += hold;
+        if self.holds.len() > self.lookahead { self.sum -= self.holds.pop_front().unwrap_or_default(); }
+        if self.index.is_multiple_of(self.lookahead as u64) { self.sum = self.holds.iter().sum(); }
+        let average = (self.sum + (self.lookahead - self.holds.len()) as f64) / self.lookahead as f64;
+        self.current = if average < self.current { average } else { self.current + (average - self.current) * self.release };
+        if self.delay.len() > (self.lookahead + DETECTION) * self.channels
+        {
+            let gain = self.current;
+            output.extend(self.delay.drain(..self.channels).map(|sample| sample * gain));
+        }
+        self.index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Create stereo float blocks of a quiet 997 Hz sine with a loud click every 100 ms, 4 s in all.
+    fn program() -> Vec<AudioBuffer>
+    {
+        (0..40_u32).map(|block|
+        {
+            let mut buffer = AudioBuffer::init_with_format(2, 32, 4800, SampleFormat::Float).unwrap();
+            buffer.set_sample_rate(Some(48000));
+            for frame in 0..4800
+            {
+                let time = (block * 4800 + frame) as f64 / 48000.0;
+                let sample = if frame == 2400 { 0.8 } else { 0.1 * (2.0 * std::f64::consts::PI * 997.0 * time).sin() };
+                for channel in 0..2 { buffer.write_sample(channel, frame, sample); }
+            }
+            buffer
+        }).collect()
+    }
+    /// Measure the integrated loudness and true peak of buffers.
+    fn measure(buffers : &[AudioBuffer]) -> (f64, f64)
+    {
+        let mut measurement = Measurement::init();
+        for buffer in buffers { measurement.process(buffer); }
+        (measurement.loudness.integrated(), Level::decibels(measurement.true_peak))
+    }
+
+    #[test]
+    fn limiter_reaches_target_under_ceiling()
+    {
+        let mut buffers = program();
+        let normalized = Normalization::init(-9.0).with_limiter(-1.0).apply(&mut buffers).unwrap();
+        assert!(normalized.limited());
+        let (loudness, true_peak) = measure(&buffers);
+        assert!((loudness + 9.0).abs() < TOLERANCE, "{loudness} LUFS");
+        assert!(true_peak <= -1.0, "{true_peak} dBTP");
+        // The report comes from the last trial pass, which differs from the stored samples by their 32-bit rounding.
+        assert!((normalized.output_loudness() - loudness).abs() < 1e-3, "{} LUFS reported", normalized.output_loudness());
+        assert!((normalized.output_true_peak() - true_peak).abs() < 1e-3, "{} dBTP reported", normalized.output_true_peak());
+    }
+    #[test]
+    fn ceiling_caps_gain()
+    {
+        let mut buffers = program();
+        let (loudness, true_peak) = measure(&buffers);
+        let normalized = Normalization::init(-9.0).with_ceiling(-1.0).apply(&mut buffers).unwrap();
+        assert!(!normalized.limited());
+        assert!((normalized.gain() - (-1.0 - true_peak)).abs() < 1e-9 && normalized.gain() < -9.0 - loudness);
+        let (output_loudness, output_true_peak) = measure(&buffers);
+        assert!((output_true_peak + 1.0).abs() < 0.01, "{output_true_peak} dBTP");
+        assert!((output_loudness - normalized.output_loudness()).abs() < 0.01 && output_loudness < -9.0, "{output_loudness} LUFS");
+    }
+    #[test]
+    fn silence_is_untouched()
+    {
+        let mut buffers = vec![AudioBuffer::init(2, 16, 4800).unwrap(); 10];
+        let normalized = Normalization::init(-23.0).with_limiter(-1.0).apply(&mut buffers).unwrap();
+        assert_eq!((normalized.gain(), normalized.limited(), normalized.loudness()), (0.0, false, f64::NEG_INFINITY));
+        assert!(buffers.iter().all(|buffer| buffer.bytes().iter().all(|byte| *byte == 0)));
+        assert_eq!(Normalization::init(-23.0).apply(&mut []).unwrap().gain(), 0.0);
+    }
+    #[test]
+    fn mixed_channel_counts_are_rejected()
+    {
+        let mut buffers = vec![AudioBuffer::init(2, 16, 480).unwrap(), AudioBuffer::init(1, 16, 480).unwrap()];
+        assert!(matches!(Normalization::init(-23.0).apply(&mut buffers), Err(Error::ChannelMismatch { expected: 2, actual: 1 })));
+    }
+    #[test]
+    fn wave_round_trips()
+    {
+        let directory = std::env::temp_dir();
+        let (input, output) = (directory.join(format!("mkaudio-{}-in.wav", std::process::id())), directory.join(format!("mkaudio-{}-out.wav", std::process::id())));
+        let conversion = Conversion::init(SampleFormat::Int, 24, 2);
+        let mut writer = WaveWriter::init(std::io::BufWriter::new(std::fs::File::create(&input).unwrap()), &conversion, 48000).unwrap();
+        for buffer in program() { writer.write_block(&buffer).unwrap(); }
+        writer.finish().unwrap();
+        let written = std::fs::read(&input).unwrap();
+        let same = directory.join(".").join(input.file_name().unwrap());
+        assert!(matches!(Normalization::init(-9.0).apply_wave(&input, &same), Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::InvalidInput));
+        assert_eq!(std::fs::read(&input).unwrap(), written);
+        let normalized = Normalization::init(-9.0).with_limiter(-1.0).apply_wave(&input, &output).unwrap();
+        let mut reader = WaveReader::init(std::io::BufReader::new(std::fs::File::open(&output).unwrap())).unwrap();
+        assert_eq!((reader.format(), reader.bit_depth(), reader.channels(), reader.sample_rate()), (SampleFormat::Int, 24, 2, 48000));
+        let mut buffers = Vec::new();
+        while let Some(buffer) = reader.read_block(WAVE_BLOCK).unwrap() { buffers.push(buffer); }
+        assert_eq!(buffers.iter().map(|buffer| buffer.size()).sum::<u32>(), 40 * 4800);
+        let (loudness, true_peak) = measure(&buffers);
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+        assert!(normalized.limited());
+        assert!((loudness + 9.0).abs() < TOLERANCE, "{loudness} LUFS");
+        assert!(true_peak <= -1.0 + 0.01, "{true_peak} dBTP");
+    }
+}
@@ -0,0 +1,149 @@
+/// Native sample type that can be stored in an AudioBuffer.
+///
+/// Integer samples are scaled by a power of two so that converting between sample types is lossless whenever the
+/// target has enough bits.
+pub trait Sample : Copy + Default + PartialEq + PartialOrd + std::fmt::Debug + Send + Sync + private::Sealed + 'static
+{
+    /// Sample format of the type.
+    const FORMAT : crate::SampleFormat;
+    /// Bit depth of the type.
+    const BIT_DEPTH : u32;
+    /// Convert into a normalized sample.
+    fn to_f64(self) -> f64;
+    /// Convert from a normalized sample.
+    fn from_f64(value : f64) -> Self;
+    /// Decode from little endian bytes.
+    fn decode(bytes : &[u8]) -> Self;
+    /// Encode into little endian bytes.
+    fn encode(self, bytes : &mut [u8]);
+    /// Convert into another sample type.
+    fn convert<S : Sample>(self) -> S { S::from_f64(self.to_f64()) }
+}
+
+/// Signed 24-bit integer sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct I24(i32);
+impl I24
+{
+    /// Smallest value of a 24-bit sample.
+    pub const MIN : Self = Self(-(1 << 23));
+    /// Largest value of a 24-bit sample.
+    pub const MAX : Self = Self((1 << 23) - 1);
+    /// Create a 24-bit sample, saturating values out of range.
+    pub fn new(value : i32) -> Self { Self(value.clamp(Self::MIN.0, Self::MAX.0)) }
+    /// Get the value of the sample.
+    pub fn get(self) -> i32 { self.0 }
+}
+impl From<I24> for i32
+{
+    fn from(value : I24) -> Self { value.0 }
+}
+
+/// Normalize an integer sample with the given number of valid bits.
+#[inline]
+pub(crate) fn int_to_f64(value : i64, bit_depth : u32) -> f64 { value as f64 / (1_i64 << (bit_depth - 1)) as f64 }
+/// Quantize a normalized sample into an integer with the given number of valid bits.
+#[inline]
+pub(crate) fn int_from_f64(value : f64, bit_depth : u32) -> i64
+{
+    let limit = 1_i64 << (bit_depth - 1);
+    ((value * limit as f64) as i64).clamp(-limit, limit - 1)
+}
+/// Quantize a normalized sample like `int_from_f64`, applying the clip policy and reporting whether it was out of range.
+pub(crate) fn int_clip(value : f64, bit_depth : u32, clip : crate::ClipPolicy) -> (i64, bool)
+{
+    let limit = 1_i64 << (bit_depth - 1);
+    let value = (value * limit as f64) as i64;
+    if (-limit..limit).contains(&value) { return (value, false); }
+    let shift = 64 - bit_depth;
+    match clip
+    {
+        crate::ClipPolicy::Wrap => ((value << shift) >> shift, true),
+        _ => (value.clamp(-limit, limit - 1), true)
+    }
+}
+
+macro_rules! int_sample
+{
+    ($type : ty, $bit_depth : expr, $from : expr, $get : expr) =>
+    {
+        impl private::Sealed for $type { }
+        impl Sample for $type
+        {
+            const FORMAT : crate::SampleFormat = crate::SampleFormat::Int;
+            const BIT_DEPTH : u32 = $bit_depth;
+            #[inline]
+            fn to_f64(self) -> f64 { int_to_f64($get(self) as i64, $bit_depth) }
+            #[inline]
+            fn from_f64(value : f64) -> Self { $from(int_from_f64(value, $bit_depth)) }
+            #[inline]
+            fn decode(bytes : &[u8]) -> Self
+            {
+                let mut data = 0_i64;
+                for (bit, byte) in bytes[..$bit_depth / 8].iter().enumerate() { data |= (*byte as i64) << (bit * 8); }
+                let shift = 64 - $bit_depth;
+                $from((data << shift) >> shift)
+            }
+            #[inline]
+            fn encode(self, bytes : &mut [u8]) { bytes[..$bit_depth / 8].copy_from_slice(&($get(self) as i64).to_le_bytes()[..$bit_depth / 8]); }
+        }
+    };
+}
+int_sample!(i8, 8, |value : i64| value as i8, |sample : i8| sample);
+int_sample!(i16, 16, |value : i64| value as i16, |sample : i16| sample);
+int_sample!(I24, 24, |value : i64| I24(value as i32), |sample : I24| sample.0);
+int_sample!(i32, 32, |value : i64| value as i32, |sample : i32| sample);
+
+impl private::Sealed for u8 { }
+impl Sample for u8
+{
+    const FORMAT : crate::SampleFormat = crate::SampleFormat::UInt;
+    const BIT_DEPTH : u32 = 8;
+    #[inline]
+    fn to_f64(self) -> f64 { int_to_f64(self as i64 - 128, 8) }
+    #[inline]
+    fn from_f64(value : f64) -> Self { (int_from_f64(value, 8) + 128) as u8 }
+    #[inline]
+    fn decode(bytes : &[u8]) -> Self { bytes[0] }
+    #[inline]
+    fn encode(self, bytes : &mut [u8]) { bytes[0] = self; }
+}
+
+impl private::Sealed for f32 { }
+impl Sample for f32
+{
+    const FORMAT : crate::SampleFormat = crate::SampleFormat::Float;
+    const BIT_DEPTH : u32 = 32;
+    #[inline]
+    fn to_f64(self) -> f64 { self as f64 }
+    #[inline]
+    fn from_f64(value : f64) -> Self { value as f32 }
+    #[inline]
+    fn decode(bytes : &[u8]) -> Self { f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+    #[inline]
+    fn encode(self, bytes : &mut [u8]) { bytes[..4].copy_from_slice(&f32::to_le_bytes(self)); }
+}
+impl private::Sealed for f64 { }
+impl Sample for f64
+{
+    const FORMAT : crate::SampleFormat = crate::SampleFormat::Float;
+    const BIT_DEPTH : u32 = 64;
+    #[inline]
+    fn to_f64(self) -> f64 { self }
+    #[inline]
+    fn from_f64(value : f64) -> Self { value }
+    #[inline]
+    fn decode(bytes : &[u8]) -> Self
+    {
+        let mut data = [0; 8];
+        data.copy_from_slice(&bytes[..8]);
+        f64::from_le_bytes(data)
+    }
+    #[inline]
+    fn encode(self, bytes : &mut [u8]) { bytes[..8].copy_from_slice(&f64::to_le_bytes(self)); }
+}
+
+mod private
+{
+    pub trait Sealed { }
+}
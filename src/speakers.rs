@@ -0,0 +1,222 @@
+/// Position of a single channel in a speaker layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelPosition
+{
+    /// Front left speaker.
+    FrontLeft,
+    /// Front right speaker.
+    FrontRight,
+    /// Front center speaker, also used for mono.
+    FrontCenter,
+    /// Low frequency effects channel.
+    LowFrequency,
+    /// Rear left speaker.
+    BackLeft,
+    /// Rear right speaker.
+    BackRight,
+    /// Side left speaker.
+    SideLeft,
+    /// Side right speaker.
+    SideRight,
+    /// Top front left speaker.
+    TopFrontLeft,
+    /// Top front right speaker.
+    TopFrontRight,
+    /// Top rear left speaker.
+    TopBackLeft,
+    /// Top rear right speaker.
+    TopBackRight,
+    /// Ambisonic component with the given ACN index.
+    Ambisonic(u32),
+    /// Channel without a speaker position, such as a microphone input.
+    Discrete(u32)
+}
+impl std::fmt::Display for ChannelPosition
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Self::FrontLeft => write!(f, "L"),
+            Self::FrontRight => write!(f, "R"),
+            Self::FrontCenter => write!(f, "C"),
+            Self::LowFrequency => write!(f, "LFE"),
+            Self::BackLeft => write!(f, "Lrs"),
+            Self::BackRight => write!(f, "Rrs"),
+            Self::SideLeft => write!(f, "Ls"),
+            Self::SideRight => write!(f, "Rs"),
+            Self::TopFrontLeft => write!(f, "Ltf"),
+            Self::TopFrontRight => write!(f, "Rtf"),
+            Self::TopBackLeft => write!(f, "Ltr"),
+            Self::TopBackRight => write!(f, "Rtr"),
+            Self::Ambisonic(index) => write!(f, "ACN{index}"),
+            Self::Discrete(index) => write!(f, "Ch{index}")
+        }
+    }
+}
+
+/// Meaning of the channels of an AudioBuffer, in the channel order of WAVE files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelLayout
+{
+    /// Single channel.
+    Mono,
+    /// Left and right.
+    Stereo,
+    /// Left, right and center.
+    Lcr,
+    /// Left, right, center, LFE, side left and side right.
+    Surround51,
+    /// Left, right, center, LFE, rear left, rear right, side left and side right.
+    Surround71,
+    /// 7.1 with top front left, top front right, top rear left and top rear right.
+    Surround714,
+    /// Ambisonics of the given order in ACN channel order.
+    Ambisonic(u32),
+    /// The given number of channels without speaker positions.
+    Discrete(u32)
+}
+impl Default for ChannelLayout
+{
+    fn default() -> Self { Self::Discrete(0) }
+}
+impl ChannelLayout
+{
+    /// Get the usual layout for a channel count: mono, stereo or discrete channels.
+    pub fn from_channels(channels : u32) -> Self
+    {
+        match channels
+        {
+            1 => Self::Mono,
+            2 => Self::Stereo,
+            _ => Self::Discrete(channels)
+        }
+    }
+    /// Get a channel count, saturating at `u32::MAX` for Ambisonic orders too high to count.
+    pub fn channels(self) -> u32
+    {
+        match self
+        {
+            Self::Ambisonic(order) => order.checked_add(1).and_then(|side| side.checked_mul(side)).unwrap_or(u32::MAX),
+            Self::Discrete(channels) => channels,
+            _ => self.speakers().len() as u32
+        }
+    }
+    /// Get the position of a channel.
+    pub fn position(self, channel : u32) -> Option<ChannelPosition>
+    {
+        if channel >= self.channels() { return None; }
+        match self
+        {
+            Self::Ambisonic(_) => Some(ChannelPosition::Ambisonic(channel)),
+            Self::Discrete(_) => Some(ChannelPosition::Discrete(channel)),
+            _ => Some(self.speakers()[channel as usize])
+        }
+    }
+    /// Iterate over the positions of every channel.
+    pub fn positions(self) -> impl Iterator<Item = ChannelPosition>
+    {
+        (0..self.channels()).filter_map(move |channel| self.position(channel))
+    }
+    /// Get the channel at a position.
+    pub fn channel_of(self, position : ChannelPosition) -> Option<u32> { self.positions().position(|other| other == position).map(|channel| channel as u32) }
+    /// Check whether the layout has a low frequency effects channel.
+    pub fn has_lfe(self) -> bool { self.channel_of(ChannelPosition::LowFrequency).is_some() }
+    /// Get the layout of a WAVE channel mask, or the usual layout of the channel count for other masks.
+    pub(crate) fn from_wave_mask(mask : u32, channels : u32) -> Self
+    {
+        let layout = match mask
+        {
+            0x4 => Self::Mono,
+            0x3 => Self::Stereo,
+            0x7 => Self::Lcr,
+            0x3F | 0x60F => Self::Surround51,
+            0x63F => Self::Surround71,
+            0x2D63F => Self::Surround714,
+            _ => return Self::from_channels(channels)
+        };
+        if layout.channels() == channels { layout } else { Self::from_channels(channels) }
+    }
+    /// Get the WAVE channel mask of the layout, 0 without speaker positions.
+    pub(crate) fn wave_mask(self) -> u32
+    {
+        match self
+        {
+            Self::Mono => 0x4,
+            Self::Stereo => 0x3,
+            Self::Lcr => 0x7,
+            Self::Surround51 => 0x60F,
+            Self::Surround71 => 0x63F,
+            Self::Surround714 => 0x2D63F,
+            Self::Ambisonic(_) | Self::Discrete(_) => 0
+        }
+    }
+    fn speakers(self) -> &'static [ChannelPosition]
+    {
+        use ChannelPosition::*;
+        match self
+        {
+            Self::Mono => &[FrontCenter],
+            Self::Stereo => &[FrontLeft, FrontRight],
+            Self::Lcr => &[FrontLeft, FrontRight, FrontCenter],
+            Self::Surround51 => &[FrontLeft, FrontRight, FrontCenter, LowFrequency, SideLeft, SideRight],
+            Self::Surround71 => &[FrontLeft, FrontRight, FrontCenter, LowFrequency, BackLeft, BackRight, SideLeft, SideRight],
+            Self::Surround714 =>
+            {
+                &[FrontLeft, FrontRight, FrontCenter, LowFrequency, BackLeft, BackRight, SideLeft, SideRight, TopFrontLeft, TopFrontRight, TopBackLeft, TopBackRight]
+            }
+            Self::Ambisonic(_) | Self::Discrete(_) => &[]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use ChannelPosition::*;
+
+    #[test]
+    fn positions_follow_wave_order()
+    {
+        assert_eq!(ChannelLayout::Surround51.positions().collect::<Vec<_>>(), [FrontLeft, FrontRight, FrontCenter, LowFrequency, SideLeft, SideRight]);
+        assert_eq!(ChannelLayout::Surround714.position(9), Some(TopFrontRight));
+        assert_eq!(ChannelLayout::Surround714.position(12), None);
+        assert_eq!(ChannelLayout::Mono.position(0), Some(FrontCenter));
+        assert_eq!(ChannelLayout::Ambisonic(1).positions().collect::<Vec<_>>(), [Ambisonic(0), Ambisonic(1), Ambisonic(2), Ambisonic(3)]);
+        assert_eq!(ChannelLayout::Discrete(3).position(2), Some(Discrete(2)));
+        assert_eq!(ChannelLayout::Surround71.channel_of(BackLeft), Some(4));
+        assert_eq!(ChannelLayout::Surround71.channel_of(SideRight), Some(7));
+        assert_eq!(ChannelLayout::Stereo.channel_of(FrontCenter), None);
+        assert_eq!(ChannelLayout::Ambisonic(2).channel_of(Ambisonic(8)), Some(8));
+        assert!(ChannelLayout::Surround51.has_lfe() && ChannelLayout::Surround714.has_lfe());
+        assert!(!ChannelLayout::Lcr.has_lfe() && !ChannelLayout::Discrete(6).has_lfe());
+    }
+    #[test]
+    fn channel_counts_saturate()
+    {
+        let counts = [(ChannelLayout::Mono, 1), (ChannelLayout::Stereo, 2), (ChannelLayout::Lcr, 3), (ChannelLayout::Surround51, 6), (ChannelLayout::Surround71, 8), (ChannelLayout::Surround714, 12)];
+        for (layout, channels) in counts { assert_eq!(layout.channels(), channels, "{layout:?}"); }
+        assert_eq!((ChannelLayout::Ambisonic(0).channels(), ChannelLayout::Ambisonic(3).channels()), (1, 16));
+        assert_eq!(ChannelLayout::Ambisonic(65534).channels(), 65535 * 65535);
+        assert_eq!((ChannelLayout::Ambisonic(65535).channels(), ChannelLayout::Ambisonic(u32::MAX).channels()), (u32::MAX, u32::MAX));
+        assert_eq!(ChannelLayout::from_channels(0), ChannelLayout::default());
+        let mut buffer = crate::AudioBuffer::init(4, 16, 1).unwrap();
+        assert!(matches!(buffer.set_channel_layout(ChannelLayout::Ambisonic(u32::MAX)), Err(crate::Error::InvalidChannelLayout { channels: 4, .. })));
+    }
+    #[test]
+    fn wave_masks_round_trip()
+    {
+        for layout in [ChannelLayout::Mono, ChannelLayout::Stereo, ChannelLayout::Lcr, ChannelLayout::Surround51, ChannelLayout::Surround71, ChannelLayout::Surround714]
+        {
+            assert_eq!(ChannelLayout::from_wave_mask(layout.wave_mask(), layout.channels()), layout);
+        }
+        // Back surround 5.1 reads as the side surround 5.1 of the same speakers.
+        assert_eq!(ChannelLayout::from_wave_mask(0x3F, 6), ChannelLayout::Surround51);
+        // Masks which do not match the channel count, or no known layout, fall back to the channel count.
+        assert_eq!(ChannelLayout::from_wave_mask(0x3, 1), ChannelLayout::Mono);
+        assert_eq!(ChannelLayout::from_wave_mask(0x33, 4), ChannelLayout::Discrete(4));
+        assert_eq!(ChannelLayout::from_wave_mask(0, 2), ChannelLayout::Stereo);
+        assert_eq!((ChannelLayout::Ambisonic(1).wave_mask(), ChannelLayout::Discrete(2).wave_mask()), (0, 0));
+    }
+}
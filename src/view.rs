@@ -0,0 +1,421 @@
+/// Read access to frames of audio, shared by AudioBuffer and its views.
+pub trait AudioRead
+{
+    /// Get a channel count.
+    fn channels(&self) -> u32;
+    /// Get a buffer size in frames.
+    fn size(&self) -> u32;
+    /// Read a single sample of the channel from the frame.
+    fn read_sample(&self, channel : u32, frame : u32) -> f64;
+    /// Read a single frame into a given slice with one sample per channel.
+    fn read_frame(&self, frame : u32, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        crate::check_len(self.channels() as usize, buffer.len())?;
+        for (channel, sample) in buffer.iter_mut().enumerate() { *sample = self.read_sample(channel as u32, frame); }
+        Ok(())
+    }
+    /// Read all frames into a given slice of interleaved frames.
+    fn read_slice(&self, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        let channels = self.channels() as usize;
+        crate::check_len(self.size() as usize * channels, buffer.len())?;
+        for (index, sample) in buffer.iter_mut().enumerate() { *sample = self.read_sample((index % channels) as u32, (index / channels) as u32); }
+        Ok(())
+    }
+    /// Read a whole channel into a given slice.
+    fn read_channel(&self, channel : u32, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, self.channels())?;
+        crate::check_len(self.size() as usize, buffer.len())?;
+        for (frame, sample) in buffer.iter_mut().enumerate() { *sample = self.read_sample(channel, frame as u32); }
+        Ok(())
+    }
+    /// Read consecutive samples of a channel from the frame on into a given slice.
+    fn read_channel_at(&self, channel : u32, frame : u32, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, self.channels())?;
+        check_span(frame, buffer.len(), self.size())?;
+        for (offset, sample) in (frame..).zip(buffer.iter_mut()) { *sample = self.read_sample(channel, offset); }
+        Ok(())
+    }
+    /// Get a channel layout, mono, stereo or discrete channels depending on the channel count unless known.
+    fn channel_layout(&self) -> crate::ChannelLayout { crate::ChannelLayout::from_channels(self.channels()) }
+    /// Get a position of the first frame in its stream, if known.
+    fn position(&self) -> Option<u64> { None }
+    /// Add the samples times a gain onto a destination, following its dither and clip policy.
+    ///
+    /// Only the frames both sides have are mixed. Equal channel layouts mix channel by channel without allocating,
+    /// other layouts allocate their standard matrix, so prepare one for `mix_into_with` on the audio thread.
+    fn mix_into<W : AudioWrite + ?Sized>(&self, destination : &mut W, gain : f64) -> crate::Result<()>
+    {
+        let (from, to) = (self.channel_layout(), destination.channel_layout());
+        if from == to && self.channels() == destination.channels()
+        {
+            return crate::mix_channels(self, destination, |output, input| if output == input { gain } else { 0.0 }, |_| 1.0);
+        }
+        self.mix_into_with(destination, &crate::MixMatrix::between(from, to), gain)
+    }
+    /// Add the samples through a matrix times a gain onto a destination without allocating, like `mix_into`.
+    fn mix_into_with<W : AudioWrite + ?Sized>(&self, destination : &mut W, matrix : &crate::MixMatrix, gain : f64) -> crate::Result<()>
+    {
+        if matrix.inputs() != self.channels() || matrix.outputs() != destination.channels()
+        {
+            return Err(crate::Error::MatrixMismatch { inputs: matrix.inputs(), outputs: matrix.outputs(), from: self.channels(), to: destination.channels() });
+        }
+        crate::mix_channels(self, destination, |output, input| matrix.gain(output, input).unwrap_or_default() * gain, |_| 1.0)
+    }
+    /// Iterate over every sample in interleaved order.
+    fn samples(&self) -> crate::Samples<'_, Self> { crate::Samples::init(self) }
+    /// Iterate over every frame.
+    fn frames(&self) -> crate::Frames<'_, Self> { crate::Frames::init(self) }
+    /// Get a single channel, panicking if it does not exist.
+    fn channel(&self, channel : u32) -> crate::Channel<'_, Self> { crate::Channel::init(self, channel) }
+}
+
+/// Write access to frames of audio, shared by AudioBuffer and its mutable views.
+pub trait AudioWrite : AudioRead
+{
+    /// Write a single sample of the channel into the frame.
+    fn write_sample(&mut self, channel : u32, frame : u32, data : f64);
+    /// Write a single sample of the channel into the frame, failing if the clip policy rejects it.
+    fn try_write_sample(&mut self, channel : u32, frame : u32, data : f64) -> crate::Result<()>
+    {
+        self.write_sample(channel, frame, data);
+        Ok(())
+    }
+    /// Write a single frame from a given slice with one sample per channel.
+    fn write_frame(&mut self, frame : u32, buffer : &[f64]) -> crate::Result<()>
+    {
+        crate::check_len(self.channels() as usize, buffer.len())?;
+        for (channel, sample) in buffer.iter().enumerate() { self.try_write_sample(channel as u32, frame, *sample)?; }
+        Ok(())
+    }
+    /// Write all frames from a given slice of interleaved frames.
+    fn write_slice(&mut self, buffer : &[f64]) -> crate::Result<()>
+    {
+        let channels = self.channels() as usize;
+        crate::check_len(self.size() as usize * channels, buffer.len())?;
+        for (index, sample) in buffer.iter().enumerate() { self.try_write_sample((index % channels) as u32, (index / channels) as u32, *sample)?; }
+        Ok(())
+    }
+    /// Write a whole channel from a given slice.
+    fn write_channel(&mut self, channel : u32, buffer : &[f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, self.channels())?;
+        crate::check_len(self.size() as usize, buffer.len())?;
+        for (frame, sample) in buffer.iter().enumerate() { self.try_write_sample(channel, frame as u32, *sample)?; }
+        Ok(())
+    }
+    /// Write consecutive samples of a channel from the frame on out of a given slice.
+    fn write_channel_at(&mut self, channel : u32, frame : u32, buffer : &[f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, self.channels())?;
+        check_span(frame, buffer.len(), self.size())?;
+        for (offset, sample) in (frame..).zip(buffer) { self.try_write_sample(channel, offset, *sample)?; }
+        Ok(())
+    }
+    /// Add the samples of a source onto these, like `AudioRead::mix_into` at unity gain.
+    fn add<A : AudioRead + ?Sized>(&mut self, other : &A) -> crate::Result<()> { other.mix_into(self, 1.0) }
+    /// Multiply every sample by a gain.
+    fn scale(&mut self, gain : f64) -> crate::Result<()> { self.apply_gain_ramp(gain, gain) }
+    /// Multiply the samples by a gain moving linearly from the start gain to the end gain.
+    ///
+    /// The end gain is reached one frame after the last one, so the ramps of consecutive blocks join without a step.
+    /// Out of range samples follow the clip policy. Nothing is rewritten for a constant gain of one.
+    fn apply_gain_ramp(&mut self, start : f64, end : f64) -> crate::Result<()>
+    {
+        if start == 1.0 && end == 1.0 { return Ok(()); }
+        let step = (end - start) / self.size().max(1) as f64;
+        crate::apply_gains(self, 0..self.size() as usize, |frame| start + step * frame as f64)
+    }
+    /// Fade in from silence over the leading frames.
+    fn fade_in(&mut self, frames : u32, curve : crate::FadeCurve) -> crate::Result<()>
+    {
+        let len = frames.min(self.size()) as usize;
+        crate::apply_gains(self, 0..len, |frame| curve.interpolate(0.0, 1.0, frame as f64 / len as f64))
+    }
+    /// Fade out into silence over the trailing frames, the last frame ending silent.
+    fn fade_out(&mut self, frames : u32, curve : crate::FadeCurve) -> crate::Result<()>
+    {
+        let (len, end) = (frames.min(self.size()) as usize, self.size() as usize);
+        crate::apply_gains(self, end - len..end, |frame| curve.interpolate(1.0, 0.0, (frame + len + 1 - end) as f64 / len as f64))
+    }
+    /// Fade out of these samples and into another source over the frames of these.
+    ///
+    /// Missing frames of the other source count as silence, and like `apply_gain_ramp` the other source reaches full
+    /// gain one frame after the last one. Different channel layouts allocate their standard matrix like `mix_into`.
+    fn crossfade<A : AudioRead + ?Sized>(&mut self, other : &A, curve : crate::FadeCurve) -> crate::Result<()>
+    {
+        let len = self.size().max(1) as f64;
+        crate::apply_gains(self, 0..self.size() as usize, |frame| curve.interpolate(1.0, 0.0, frame as f64 / len))?;
+        let envelope = |frame : usize| curve.interpolate(0.0, 1.0, frame as f64 / len);
+        let (from, to) = (other.channel_layout(), self.channel_layout());
+        if from == to && other.channels() == self.channels()
+        {
+            return crate::mix_channels(other, self, |output, input| if output == input { 1.0 } else { 0.0 }, envelope);
+        }
+        let matrix = crate::MixMatrix::between(from, to);
+        crate::mix_channels(other, self, |output, input| matrix.gain(output, input).unwrap_or_default(), envelope)
+    }
+    /// Multiply every frame by the gain of an envelope, taking the position of the first frame as its first frame.
+    ///
+    /// Samples without a position start at frame 0. Nothing is rewritten while the gain stays at one.
+    fn apply_envelope(&mut self, envelope : &crate::GainEnvelope) -> crate::Result<()>
+    {
+        let position = self.position().unwrap_or_default();
+        if envelope.is_constant_from(position) { return self.scale(envelope.gain_at(position)); }
+        crate::apply_gains(self, 0..self.size() as usize, |frame| envelope.gain_at(position + frame as u64))
+    }
+    /// Copy every sample from a source with the same channel and frame count.
+    fn copy_from<A : AudioRead + ?Sized>(&mut self, source : &A) -> crate::Result<()>
+    {
+        crate::check_len(self.channels() as usize, source.channels() as usize)?;
+        crate::check_len(self.size() as usize, source.size() as usize)?;
+        for frame in 0..self.size()
+        {
+            for channel in 0..self.channels() { self.try_write_sample(channel, frame, source.read_sample(channel, frame))?; }
+        }
+        Ok(())
+    }
+    /// Add a value onto a single sample of the channel in the frame.
+    fn mix_sample(&mut self, channel : u32, frame : u32, data : f64)
+    {
+        let mixed = self.read_sample(channel, frame) + data;
+        self.write_sample(channel, frame, mixed);
+    }
+    /// Add a given slice of interleaved frames onto all frames.
+    fn mix_slice(&mut self, buffer : &[f64]) -> crate::Result<()>
+    {
+        let channels = self.channels() as usize;
+        crate::check_len(self.size() as usize * channels, buffer.len())?;
+        for (index, sample) in buffer.iter().enumerate()
+        {
+            let (channel, frame) = ((index % channels) as u32, (index / channels) as u32);
+            let mixed = self.read_sample(channel, frame) + sample;
+            self.try_write_sample(channel, frame, mixed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Borrowed window over a range of frames and channels of an AudioBuffer.
+#[derive(Clone)]
+pub struct AudioView<'a>
+{
+    buffer : &'a crate::AudioBuffer,
+    frames : std::ops::Range<u32>,
+    channels : std::ops::Range<u32>
+}
+impl<'a> AudioView<'a>
+{
+    pub(crate) fn init(buffer : &'a crate::AudioBuffer, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> crate::Result<Self>
+    {
+        check_range(&frames, buffer.size())?;
+        check_range(&channels, buffer.channels())?;
+        Ok(Self { buffer, frames, channels })
+    }
+    /// Get a window over a range of frames and channels of this view.
+    pub fn view(&self, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> crate::Result<AudioView<'a>>
+    {
+        check_range(&frames, self.size())?;
+        check_range(&channels, AudioRead::channels(self))?;
+        Ok(Self { buffer: self.buffer, frames: offset(&frames, self.frames.start), channels: offset(&channels, self.channels.start) })
+    }
+    /// Get the first frame of the view in the underlying buffer.
+    pub fn frame_offset(&self) -> u32 { self.frames.start }
+    /// Get the first channel of the view in the underlying buffer.
+    pub fn channel_offset(&self) -> u32 { self.channels.start }
+}
+impl AudioRead for AudioView<'_>
+{
+    fn channels(&self) -> u32 { self.channels.len() as u32 }
+    fn size(&self) -> u32 { self.frames.len() as u32 }
+    fn read_sample(&self, channel : u32, frame : u32) -> f64
+    {
+        assert!(channel < AudioRead::channels(self) && frame < self.size(), "Sample is out of range.");
+        self.buffer.read_sample(self.channels.start + channel, self.frames.start + frame)
+    }
+    fn read_channel_at(&self, channel : u32, frame : u32, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, AudioRead::channels(self))?;
+        check_span(frame, buffer.len(), self.size())?;
+        self.buffer.read_channel_at(self.channels.start + channel, self.frames.start + frame, buffer)
+    }
+    fn channel_layout(&self) -> crate::ChannelLayout { layout_of(self.buffer, &self.channels) }
+    fn position(&self) -> Option<u64> { self.buffer.position().map(|position| position + self.frames.start as u64) }
+}
+
+/// Mutably borrowed window over a range of frames and channels of an AudioBuffer.
+pub struct AudioViewMut<'a>
+{
+    buffer : &'a mut crate::AudioBuffer,
+    frames : std::ops::Range<u32>,
+    channels : std::ops::Range<u32>
+}
+impl<'a> AudioViewMut<'a>
+{
+    pub(crate) fn init(buffer : &'a mut crate::AudioBuffer, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> crate::Result<Self>
+    {
+        check_range(&frames, buffer.size())?;
+        check_range(&channels, buffer.channels())?;
+        Ok(Self { buffer, frames, channels })
+    }
+    /// Get a window over a range of frames and channels of this view.
+    pub fn view(&self, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> crate::Result<AudioView<'_>>
+    {
+        check_range(&frames, self.size())?;
+        check_range(&channels, AudioRead::channels(self))?;
+        AudioView::init(self.buffer, offset(&frames, self.frames.start), offset(&channels, self.channels.start))
+    }
+    /// Get a mutable window over a range of frames and channels of this view.
+    pub fn view_mut(&mut self, frames : std::ops::Range<u32>, channels : std::ops::Range<u32>) -> crate::Result<AudioViewMut<'_>>
+    {
+        check_range(&frames, self.size())?;
+        check_range(&channels, AudioRead::channels(self))?;
+        let (frames, channels) = (offset(&frames, self.frames.start), offset(&channels, self.channels.start));
+        AudioViewMut::init(self.buffer, frames, channels)
+    }
+    /// Get the first frame of the view in the underlying buffer.
+    pub fn frame_offset(&self) -> u32 { self.frames.start }
+    /// Get the first channel of the view in the underlying buffer.
+    pub fn channel_offset(&self) -> u32 { self.channels.start }
+}
+impl AudioRead for AudioViewMut<'_>
+{
+    fn channels(&self) -> u32 { self.channels.len() as u32 }
+    fn size(&self) -> u32 { self.frames.len() as u32 }
+    fn read_sample(&self, channel : u32, frame : u32) -> f64
+    {
+        assert!(channel < AudioRead::channels(self) && frame < self.size(), "Sample is out of range.");
+        self.buffer.read_sample(self.channels.start + channel, self.frames.start + frame)
+    }
+    fn read_channel_at(&self, channel : u32, frame : u32, buffer : &mut [f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, AudioRead::channels(self))?;
+        check_span(frame, buffer.len(), self.size())?;
+        self.buffer.read_channel_at(self.channels.start + channel, self.frames.start + frame, buffer)
+    }
+    fn channel_layout(&self) -> crate::ChannelLayout { layout_of(self.buffer, &self.channels) }
+    fn position(&self) -> Option<u64> { self.buffer.position().map(|position| position + self.frames.start as u64) }
+}
+impl AudioWrite for AudioViewMut<'_>
+{
+    fn write_sample(&mut self, channel : u32, frame : u32, data : f64)
+    {
+        assert!(channel < AudioRead::channels(self) && frame < self.size(), "Sample is out of range.");
+        self.buffer.write_sample(self.channels.start + channel, self.frames.start + frame, data);
+    }
+    fn try_write_sample(&mut self, channel : u32, frame : u32, data : f64) -> crate::Result<()>
+    {
+        assert!(channel < AudioRead::channels(self) && frame < self.size(), "Sample is out of range.");
+        self.buffer.try_write_sample(self.channels.start + channel, self.frames.start + frame, data)
+            .map_err(|_| crate::Error::Clipped { channel, frame })
+    }
+    fn write_channel_at(&mut self, channel : u32, frame : u32, buffer : &[f64]) -> crate::Result<()>
+    {
+        crate::check_channel(channel, AudioRead::channels(self))?;
+        check_span(frame, buffer.len(), self.size())?;
+        let (channels, frames) = (self.channels.start, self.frames.start);
+        self.buffer.write_channel_at(channels + channel, frames + frame, buffer).map_err(|error| match error
+        {
+            crate::Error::Clipped { channel, frame } => crate::Error::Clipped { channel: channel - channels, frame: frame - frames },
+            error => error
+        })
+    }
+}
+
+fn check_range(range : &std::ops::Range<u32>, len : u32) -> crate::Result<()>
+{
+    if range.start <= range.end && range.end <= len { Ok(()) }
+    else { Err(crate::Error::RangeOutOfBounds { start: range.start, end: range.end, len }) }
+}
+/// Check that a number of frames from the given frame on fits into the size.
+pub(crate) fn check_span(frame : u32, len : usize, size : u32) -> crate::Result<()>
+{
+    let end = u32::try_from(len).ok().and_then(|len| frame.checked_add(len)).unwrap_or(u32::MAX);
+    check_range(&(frame..end), size)
+}
+/// Get the channel layout of a view, the one of the buffer when it covers every channel.
+fn layout_of(buffer : &crate::AudioBuffer, channels : &std::ops::Range<u32>) -> crate::ChannelLayout
+{
+    if channels.start == 0 && channels.end == buffer.channels() { buffer.channel_layout() } else { crate::ChannelLayout::from_channels(channels.len() as u32) }
+}
+fn offset(range : &std::ops::Range<u32>, start : u32) -> std::ops::Range<u32> { range.start + start..range.end + start }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Create a buffer of 4 channels and 6 frames whose samples tell their channel and frame.
+    fn numbered(layout : crate::Layout) -> crate::AudioBuffer
+    {
+        let mut buffer = crate::AudioBuffer::init(4, 16, 6).unwrap();
+        buffer.set_layout(layout);
+        for frame in 0..6
+        {
+            for channel in 0..4 { buffer.write_sample(channel, frame, number(channel, frame)); }
+        }
+        buffer
+    }
+    fn number(channel : u32, frame : u32) -> f64 { (channel * 16 + frame + 1) as f64 / 256.0 }
+
+    #[test]
+    fn views_of_views_add_their_offsets()
+    {
+        let buffer = numbered(crate::Layout::Planar);
+        let view = buffer.view(1..5, 1..4).unwrap();
+        let inner = view.view(2..4, 1..3).unwrap();
+        assert_eq!((inner.frame_offset(), inner.channel_offset(), inner.size(), inner.channels()), (3, 2, 2, 2));
+        assert_eq!((inner.read_sample(0, 0), inner.read_sample(1, 1)), (number(2, 3), number(3, 4)));
+        let mut buffer = numbered(crate::Layout::Interleaved);
+        let mut view = buffer.view_mut(1..6, 1..3).unwrap();
+        let mut inner = view.view_mut(1..3, 1..2).unwrap();
+        assert_eq!((inner.frame_offset(), inner.channel_offset()), (2, 2));
+        inner.write_channel(0, &[0.5, -0.5]).unwrap();
+        let copy = view.view(1..3, 1..2).unwrap();
+        assert_eq!((copy.frame_offset(), copy.read_sample(0, 1)), (2, -0.5));
+        assert_eq!((buffer.read_sample(2, 2), buffer.read_sample(2, 3), buffer.read_sample(2, 4)), (0.5, -0.5, number(2, 4)));
+    }
+    #[test]
+    fn bad_ranges_are_rejected()
+    {
+        let mut buffer = numbered(crate::Layout::Interleaved);
+        assert!(matches!(buffer.view(0..7, 0..4), Err(crate::Error::RangeOutOfBounds { start: 0, end: 7, len: 6 })));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 3..2;
+        assert!(matches!(buffer.view(reversed, 0..4), Err(crate::Error::RangeOutOfBounds { start: 3, end: 2, len: 6 })));
+        // Nested ranges are checked against the view, not the buffer.
+        let view = buffer.view(2..6, 0..2).unwrap();
+        assert!(matches!(view.view(0..5, 0..2), Err(crate::Error::RangeOutOfBounds { start: 0, end: 5, len: 4 })));
+        assert!(matches!(view.view(0..4, 1..3), Err(crate::Error::RangeOutOfBounds { start: 1, end: 3, len: 2 })));
+        let mut view = buffer.view_mut(0..6, 2..4).unwrap();
+        assert!(matches!(view.view_mut(0..6, 0..3), Err(crate::Error::RangeOutOfBounds { start: 0, end: 3, len: 2 })));
+        assert!(matches!(view.read_channel_at(0, 4, &mut [0.0; 3]), Err(crate::Error::RangeOutOfBounds { start: 4, end: 7, len: 6 })));
+        assert!(matches!(view.write_channel(2, &[0.0; 6]), Err(crate::Error::ChannelOutOfRange { channel: 2, channels: 2 })));
+        assert!(buffer.view(6..6, 4..4).is_ok());
+    }
+    #[test]
+    fn slices_and_channels_go_through_views()
+    {
+        for layout in [crate::Layout::Interleaved, crate::Layout::Planar]
+        {
+            let mut buffer = numbered(layout);
+            let view = buffer.view(2..5, 1..3).unwrap();
+            let mut slice = vec![0.0; 6];
+            view.read_slice(&mut slice).unwrap();
+            assert_eq!(slice, [number(1, 2), number(2, 2), number(1, 3), number(2, 3), number(1, 4), number(2, 4)], "{layout:?}");
+            let mut channel = vec![0.0; 3];
+            view.read_channel(1, &mut channel).unwrap();
+            assert_eq!(channel, [number(2, 2), number(2, 3), number(2, 4)], "{layout:?}");
+            assert!(matches!(view.read_slice(&mut [0.0; 5]), Err(crate::Error::LengthMismatch { expected: 6, actual: 5 })));
+            let mut view = buffer.view_mut(2..5, 1..3).unwrap();
+            view.write_slice(&[0.125, 0.25, 0.375, 0.5, 0.625, 0.75]).unwrap();
+            view.read_channel(0, &mut channel).unwrap();
+            assert_eq!(channel, [0.125, 0.375, 0.625], "{layout:?}");
+            // Samples around the view stay as they were.
+            assert_eq!((buffer.read_sample(0, 2), buffer.read_sample(3, 2), buffer.read_sample(1, 1), buffer.read_sample(1, 5)), (number(0, 2), number(3, 2), number(1, 1), number(1, 5)), "{layout:?}");
+            assert_eq!((buffer.read_sample(1, 2), buffer.read_sample(2, 4)), (0.125, 0.75), "{layout:?}");
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Reading and writing WAVE files block by block.
+
+use crate::{AudioBuffer, ChannelLayout, Conversion, Error, Justify, Layout, Result, SampleFormat};
+
+/// Streaming reader of the samples of a WAVE file.
+///
+/// Integer, floating point and G.711 samples are read as stored, in plain or extensible format chunks. The channel
+/// mask of an extensible file gives the channel layout when it matches a known one.
+pub struct WaveReader<R : std::io::Read>
+{
+    reader : R,
+    format : SampleFormat,
+    bit_depth : u32,
+    container : u32,
+    channels : u32,
+    sample_rate : u32,
+    speakers : ChannelLayout,
+    remaining : u64,
+    position : u64
+}
+impl<R : std::io::Read> WaveReader<R>
+{
+    /// Read the header up to the start of the samples.
+    pub fn init(mut reader : R) -> Result<Self>
+    {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" { return Err(Error::InvalidWave("missing RIFF WAVE header")); }
+        let mut format = None;
+        loop
+        {
+            let mut chunk = [0; 8];
+            reader.read_exact(&mut chunk)?;
+            let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+            match &chunk[..4]
+            {
+                b"fmt " =>
+                {
+                    let mut data = vec![0; size as usize];
+                    reader.read_exact(&mut data)?;
+                    format = Some(data);
+                    skip(&mut reader, size % 2)?;
+                }
+                b"data" =>
+                {
+                    let format = format.ok_or(Error::InvalidWave("data chunk before fmt chunk"))?;
+                    return Self::with_format(reader, &format, size);
+                }
+                _ => skip(&mut reader, size + size % 2)?
+            }
+        }
+    }
+    /// Get a sample format.
+    pub fn format(&self) -> SampleFormat { self.format }
+    /// Get a valid bit depth.
+    pub fn bit_depth(&self) -> u32 { self.bit_depth }
+    /// Get a channel count.
+    pub fn channels(&self) -> u32 { self.channels }
+    /// Get a sample rate.
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+    /// Get a channel layout.
+    pub fn channel_layout(&self) -> ChannelLayout { self.speakers }
+    /// Get the number of frames left to read, as declared by the header.
+    pub fn remaining(&self) -> u64 { self.remaining / self.frame_size() as u64 }
+    /// Read up to the given number of frames into a new buffer, or None at the end of the samples.
+    ///
+    /// The buffer carries the sample rate, the channel layout and its position in the file.
+    pub fn read_block(&mut self, frames : u32) -> Result<Option<AudioBuffer>>
+    {
+        let frame_size = self.frame_size() as u64;
+        let frames = (frames as u64).min(self.remaining / frame_size) as u32;
+        if frames == 0 { return Ok(None); }
+        let mut buffer = AudioBuffer::init_with_format(self.channels, self.bit_depth, frames, self.format)?;
+        if buffer.container != self.container { buffer.set_container(self.container, Justify::Left)?; }
+        let bytes = buffer.bytes_mut();
+        let mut len = 0;
+        while len < bytes.len()
+        {
+            match self.reader.read(&mut bytes[len..])
+            {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into())
+            }
+        }
+        let frames = (len as u64 / frame_size) as u32;
+        self.remaining = if len < bytes.len() { 0 } else { self.remaining - len as u64 };
+        if frames == 0 { return Ok(None); }
+        buffer.resize(frames)?;
+        buffer.speakers = self.speakers;
+        buffer.sample_rate = Some(self.sample_rate);
+        buffer.position = Some(self.position);
+        self.position += frames as u64;
+        Ok(Some(buffer))
+    }
+    fn with_format(reader : R, data : &[u8], remaining : u64) -> Result<Self>
+    {
+        if data.len() < 16 { return Err(Error::InvalidWave("fmt chunk is too short")); }
+        let word = |offset : usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let long = |offset : usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let (mut tag, channels, sample_rate, block_align, bits) = (word(0), word(2) as u32, long(4), word(12) as u32, word(14) as u32);
+        let (mut bit_depth, mut mask) = (bits, 0);
+        if tag == 0xFFFE
+        {
+            if data.len() < 40 { return Err(Error::InvalidWave("extensible fmt chunk is too short")); }
+            if word(18) != 0 { bit_depth = word(18) as u32; }
+            mask = long(20);
+            tag = word(24);
+        }
+        if channels == 0 || block_align == 0 || !block_align.is_multiple_of(channels) { return Err(Error::InvalidWave("invalid block alignment")); }
+        let container = block_align / channels * 8;
+        let format = match (tag, container)
+        {
+            (1, 8) => SampleFormat::UInt,
+            (1, _) => SampleFormat::Int,
+            (3, _) => SampleFormat::Float,
+            (6, _) => SampleFormat::ALaw,
+            (7, _) => SampleFormat::MuLaw,
+            _ => return Err(Error::InvalidWave("unsupported sample format"))
+        };
+        if !format.supports(bit_depth) { return Err(Error::InvalidBitDepth { format, bit_depth }); }
+        if bit_depth > container { return Err(Error::InvalidContainer { format, bit_depth, container }); }
+        let speakers = ChannelLayout::from_wave_mask(mask, channels);
+        Ok(Self { reader, format, bit_depth, container, channels, sample_rate, speakers, remaining, position: 0 })
+    }
+    fn frame_size(&self) -> u32 { self.container / 8 * self.channels }
+}
+
+/// Streaming writer of the samples of a WAVE file.
+///
+/// Blocks are converted into the sample format, bit depth and channel layout of the writer, following its clip policy
+/// and dither. More than two channels or a bit depth narrower than its container use the extensible format chunk.
+pub struct WaveWriter<W : std::io::Write + std::io::Seek>
+{
+    writer : W,
+    block : AudioBuffer,
+    start : u64,
+    len : u64
+}
+impl<W : std::io::Write + std::io::Seek> WaveWriter<W>
+{
+    /// Write the header for samples of the conversion at the sample rate.
+    pub fn init(mut writer : W, conversion : &Conversion, sample_rate : u32) -> Result<Self>
+    {
+        let block = conversion.with_layout(Layout::Interleaved).destination(0)?;
+        let (format, bit_depth, container, channels) = (block.format, block.bit_depth, block.container, block.channels);
+        let tag : u16 = match format
+        {
+            SampleFormat::Int if container > 8 => 1,
+            SampleFormat::UInt if container == 8 => 1,
+            SampleFormat::Float => 3,
+            SampleFormat::ALaw => 6,
+            SampleFormat::MuLaw => 7,
+            _ => return Err(Error::InvalidWave("integer samples must be unsigned in 8 bits and signed above"))
+        };
+        if channels == 0 || channels > u16::MAX as u32 { return Err(Error::InvalidWave("unsupported channel count")); }
+        let align = container / 8 * channels;
+        let mut header = Vec::with_capacity(68);
+        header.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+        let extensible = channels > 2 || bit_depth != container;
+        header.extend_from_slice(&(if extensible { 40_u32 } else { 16 }).to_le_bytes());
+        header.extend_from_slice(&(if extensible { 0xFFFE } else { tag }).to_le_bytes());
+        header.extend_from_slice(&(channels as u16).to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * align).to_le_bytes());
+        header.extend_from_slice(&(align as u16).to_le_bytes());
+        header.extend_from_slice(&(container as u16).to_le_bytes());
+        if extensible
+        {
+            header.extend_from_slice(&22_u16.to_le_bytes());
+            header.extend_from_slice(&(bit_depth as u16).to_le_bytes());
+            header.extend_from_slice(&block.speakers.wave_mask().to_le_bytes());
+            header.extend_from_slice(&tag.to_le_bytes());
+            header.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        }
+        header.extend_from_slice(b"data\0\0\0\0");
+        let start = writer.stream_position()?;
+        writer.write_all(&header)?;
+        Ok(Self { writer, block, start, len: 0 })
+    }
+    /// Convert and append the frames of a buffer.
+    pub fn write_block(&mut self, buffer : &AudioBuffer) -> Result<()>
+    {
+        let size = buffer.buffer_size as u64 * self.block.container as u64 / 8 * self.block.channels as u64;
+        if self.len + size > u32::MAX as u64 - 60 { return Err(Error::InvalidWave("samples exceed the size limit of 4 GiB")); }
+        buffer.convert_into(&mut self.block)?;
+        self.writer.write_all(self.block.bytes())?;
+        self.len += size;
+        Ok(())
+    }
+    /// Fill in the sizes of the header and get the writer back, positioned after the samples.
+    pub fn finish(mut self) -> Result<W>
+    {
+        if self.len % 2 == 1 { self.writer.write_all(&[0])?; }
+        let end = self.writer.stream_position()?;
+        let header = if self.block.channels > 2 || self.block.bit_depth != self.block.container { 68 } else { 44 };
+        self.writer.seek(std::io::SeekFrom::Start(self.start + 4))?;
+        self.writer.write_all(&((end - self.start - 8) as u32).to_le_bytes())?;
+        self.writer.seek(std::io::SeekFrom::Start(self.start + header - 4))?;
+        self.writer.write_all(&(self.len as u32).to_le_bytes())?;
+        self.writer.seek(std::io::SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn skip(reader : &mut impl std::io::Read, len : u64) -> Result<()>
+{
+    let skipped = std::io::copy(&mut std::io::Read::take(reader, len), &mut std::io::sink())?;
+    if skipped < len { return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into())); }
+    Ok(())
+}